@@ -0,0 +1 @@
+alter table download_info drop column variant;
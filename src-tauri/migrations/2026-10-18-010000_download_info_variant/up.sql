@@ -0,0 +1 @@
+alter table download_info add column variant TEXT; --媒体流选择; highestBandwidth resolution:1920x1080 maxHeight:720
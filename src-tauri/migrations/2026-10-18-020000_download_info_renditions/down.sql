@@ -0,0 +1,2 @@
+alter table download_info drop column subtitle_renditions;
+alter table download_info drop column audio_renditions;
@@ -0,0 +1,2 @@
+alter table download_info add column audio_renditions TEXT; --音轨选择，多个名称或语言用逗号分隔; all 全部
+alter table download_info add column subtitle_renditions TEXT; --字幕选择，多个名称或语言用逗号分隔; all 全部
@@ -0,0 +1,12 @@
+create table if not exists download_queue (
+	id TEXT PRIMARY KEY NOT NULL , --主键id
+	download_id TEXT NOT NULL  , --下载记录id
+	priority INTEGER NOT NULL DEFAULT 0 , --优先级;数值越大越先下载
+	position REAL NOT NULL  , --同优先级内的排序
+	`status` TEXT NOT NULL  , --状态;wait 等待 running 下载中
+	attempt_count INTEGER NOT NULL DEFAULT 0 , --已启动下载的次数
+	enqueue_time TEXT NOT NULL, --入队时间
+	update_time TEXT --更新时间
+);
+
+create UNIQUE INDEX idx_download_queue_download_id on download_queue(`download_id`);
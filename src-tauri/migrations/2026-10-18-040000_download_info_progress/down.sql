@@ -0,0 +1,5 @@
+alter table download_info drop column eta;
+alter table download_info drop column average_speed;
+alter table download_info drop column speed;
+alter table download_info drop column total_bytes;
+alter table download_info drop column downloaded_bytes;
@@ -0,0 +1,5 @@
+alter table download_info add column downloaded_bytes BIGINT NOT NULL DEFAULT 0; --已下载的字节数
+alter table download_info add column total_bytes BIGINT; --估算的总字节数
+alter table download_info add column speed BIGINT NOT NULL DEFAULT 0; --当前下载速度，字节每秒
+alter table download_info add column average_speed BIGINT NOT NULL DEFAULT 0; --平均下载速度，字节每秒
+alter table download_info add column eta BIGINT; --预计剩余时间，单位秒
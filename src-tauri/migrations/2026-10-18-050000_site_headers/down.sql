@@ -0,0 +1 @@
+alter table site drop column headers;
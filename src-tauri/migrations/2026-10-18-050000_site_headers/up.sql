@@ -0,0 +1 @@
+alter table site add column headers TEXT; --按域名配置的请求头; [{"host":"*.example.com","headers":{"Referer":"https://example.com/"}}]
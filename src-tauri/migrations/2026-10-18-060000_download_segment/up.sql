@@ -0,0 +1,11 @@
+create table if not exists download_segment (
+	download_id TEXT NOT NULL , --下载记录id
+	file_name TEXT NOT NULL , --分片文件路径
+	`status` TEXT NOT NULL , --状态;success 已完成 failed 多次重试后仍失败
+	size BIGINT NOT NULL DEFAULT 0 , --分片文件的字节数
+	checksum TEXT , --分片文件的 CRC32
+	attempts INTEGER NOT NULL DEFAULT 0 , --最近一轮下载的尝试次数
+	error TEXT , --失败原因
+	update_time TEXT , --更新时间
+	PRIMARY KEY (download_id, file_name)
+);
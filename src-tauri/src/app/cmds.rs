@@ -20,6 +20,8 @@ use tokio::{io::AsyncWriteExt, sync::watch, time::interval};
 use url::Url;
 use zip::ZipArchive;
 
+use crate::download::bandwidth::{refresh_bandwidth, throttle};
+use crate::download::site_headers::url_site_headers;
 use crate::utils::choose_user_agent;
 use crate::utils::{self, create_request_builder};
 
@@ -191,7 +193,11 @@ pub async fn download_file_task_async(
         if let Err(e) = async {
             // 创建 HTTP 客户端
             let client = reqwest::Client::new();
-            let response = client.get(&download_task_info.download_url).send().await?;
+            let response = client
+                .get(&download_task_info.download_url)
+                .headers(url_site_headers(&download_task_info.download_url))
+                .send()
+                .await?;
 
             // 获取文件大小
             let total_size = response
@@ -235,6 +241,7 @@ pub async fn download_file_task_async(
 
                     loop {
                         interval.tick().await; // **每秒触发**
+                        refresh_bandwidth(None); // 限速配置修改后立即生效
 
                         let downloaded = *rx.borrow(); // 获取最新的下载进度
                         let speed = (downloaded - last_downloaded) as f64 / 1024.0 / 1024.0; // MB/s
@@ -257,7 +264,9 @@ pub async fn download_file_task_async(
                 }
             });
 
+            refresh_bandwidth(None);
             while let Ok(Some(chunk)) = stream.try_next().await {
+                throttle(None, chunk.len()).await;
                 file.write_all(&chunk).await?;
                 downloaded += chunk.len() as u64;
                 tx.send(downloaded).ok(); // **更新进度**
@@ -346,7 +355,11 @@ pub async fn download_miniserve_task_async(
         if let Err(e) = async {
             // 创建 HTTP 客户端
             let client = reqwest::Client::new();
-            let response = client.get(&download_task_info.download_url).send().await?;
+            let response = client
+                .get(&download_task_info.download_url)
+                .headers(url_site_headers(&download_task_info.download_url))
+                .send()
+                .await?;
 
             // 获取文件大小
             let total_size = response
@@ -389,6 +402,7 @@ pub async fn download_miniserve_task_async(
 
                     loop {
                         interval.tick().await; // **每秒触发**
+                        refresh_bandwidth(None); // 限速配置修改后立即生效
 
                         let downloaded = *rx.borrow(); // 获取最新的下载进度
                         let speed = (downloaded - last_downloaded) as f64 / 1024.0 / 1024.0; // MB/s
@@ -411,7 +425,9 @@ pub async fn download_miniserve_task_async(
                 }
             });
 
+            refresh_bandwidth(None);
             while let Ok(Some(chunk)) = stream.try_next().await {
+                throttle(None, chunk.len()).await;
                 file.write_all(&chunk).await?;
                 downloaded += chunk.len() as u64;
                 tx.send(downloaded).ok(); // **更新进度**
@@ -515,7 +531,7 @@ async fn is_miniserve_service_ready(port: u16) -> bool {
     
     let url = format!("http://127.0.0.1:{}", port);
     
-    match client.get(&url).send().await {
+    match client.get(&url).headers(url_site_headers(&url)).send().await {
         Ok(response) => {
             // 检查响应状态码，200表示服务正常
             response.status().is_success()
@@ -896,7 +912,12 @@ pub async fn github_repos_info_version(
     );
     client_builder = client_builder.default_headers(headers);
     let client = client_builder.build().unwrap();
-    let response = client.get(url).send().await.unwrap();
+    let response = client
+        .get(&url)
+        .headers(url_site_headers(&url))
+        .send()
+        .await
+        .unwrap();
     match response.text().await {
         Ok(d) => {
             let dd = serde_json::from_str(&d);
@@ -0,0 +1,444 @@
+use std::collections::{BTreeSet, HashMap};
+
+use m3u8_rs::{Key, KeyMethod, Map, MediaPlaylist, MediaSegment};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::conf::get;
+
+use super::m3u8_encrypt_key::sequence_iv;
+
+/// 广告过滤规则，按播放列表地址的域名匹配，host 为 * 时对所有站点生效
+///
+/// 多 CDN 或多目录的正片也会出现域名和目录不同的不连续块，
+/// 所以 checkHost 和 checkPath 默认关闭，需要在站点规则中显式开启
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AdFilterRule {
+    pub host: String,
+    pub enabled: bool,
+    // 总时长超过该值的不连续块视为正片，单位秒
+    pub max_ad_duration: f32,
+    // 不连续块的分片域名与正片不同时视为广告
+    pub check_host: bool,
+    // 不连续块的分片目录与正片不同时视为广告
+    pub check_path: bool,
+    // 广告分片的时长特征，不连续块中所有分片的时长都命中时视为广告
+    pub durations: Vec<f32>,
+    // 分片地址包含任一关键字时直接过滤
+    pub patterns: Vec<String>,
+}
+
+impl Default for AdFilterRule {
+    fn default() -> Self {
+        Self {
+            host: "*".to_string(),
+            enabled: true,
+            max_ad_duration: 120.0,
+            check_host: false,
+            check_path: false,
+            durations: Vec::new(),
+            patterns: Vec::new(),
+        }
+    }
+}
+
+// 分片时长的比较精度
+const DURATION_EPSILON: f32 = 0.001;
+
+/// adFilterEnabled 总开关，默认关闭，adFilterRules 按站点配置的规则，没有匹配的规则时不过滤
+pub fn ad_filter_rule(playlist_url: &Url) -> Option<AdFilterRule> {
+    if !matches!(get("adFilterEnabled"), Some(Value::Bool(true))) {
+        return None;
+    }
+    let rules: Vec<AdFilterRule> = get("adFilterRules")
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default();
+    let host = playlist_url.host_str().unwrap_or_default();
+    let rule = rules
+        .iter()
+        .find(|r| host == r.host || host.ends_with(&format!(".{}", r.host)))
+        .or_else(|| rules.iter().find(|r| r.host == "*"))
+        .cloned()?;
+    rule.enabled.then_some(rule)
+}
+
+/// 返回判定为广告的分片在播放列表中的位置
+///
+/// 按 EXT-X-DISCONTINUITY 把播放列表分成多个块，总时长最长的块作为正片，
+/// 其他较短的块根据域名、目录和时长特征判断是否为插入的广告
+pub fn detect_ads(
+    media_play_list: &MediaPlaylist,
+    playlist_url: &Url,
+    rule: &AdFilterRule,
+) -> BTreeSet<usize> {
+    let segments = &media_play_list.segments;
+    let mut ads = BTreeSet::new();
+    if segments.is_empty() {
+        return ads;
+    }
+    let locations: Vec<(String, String)> = segments
+        .iter()
+        .map(|s| segment_location(playlist_url, s))
+        .collect();
+
+    for (pos, segment) in segments.iter().enumerate() {
+        if rule
+            .patterns
+            .iter()
+            .any(|p| segment.uri.contains(p.as_str()))
+        {
+            ads.insert(pos);
+        }
+    }
+
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for (pos, segment) in segments.iter().enumerate().skip(1) {
+        if segment.discontinuity {
+            blocks.push(start..pos);
+            start = pos;
+        }
+    }
+    blocks.push(start..segments.len());
+    if blocks.len() < 2 {
+        return ads;
+    }
+
+    let block_duration = |block: &std::ops::Range<usize>| -> f32 {
+        segments[block.clone()].iter().map(|s| s.duration).sum()
+    };
+    let main_block = blocks
+        .iter()
+        .max_by(|a, b| block_duration(a).total_cmp(&block_duration(b)))
+        .cloned()
+        .unwrap_or_default();
+    let (main_host, main_dir) = most_common(&locations[main_block.clone()]);
+
+    for block in blocks.iter().filter(|b| **b != main_block) {
+        if block_duration(block) > rule.max_ad_duration {
+            continue;
+        }
+        let block_locations = &locations[block.clone()];
+        let other_host =
+            rule.check_host && block_locations.iter().all(|(host, _)| *host != main_host);
+        let other_dir = rule.check_path && block_locations.iter().all(|(_, dir)| *dir != main_dir);
+        let fingerprint = !rule.durations.is_empty()
+            && segments[block.clone()].iter().all(|s| {
+                rule.durations
+                    .iter()
+                    .any(|d| (s.duration - d).abs() < DURATION_EPSILON)
+            });
+        if other_host || other_dir || fingerprint {
+            ads.extend(block.clone());
+        }
+    }
+    ads
+}
+
+/// 删除广告分片，被删除分片上的密钥和初始化分片移到后面保留的分片上，
+/// 分片位置变化后未指定 IV 的 AES-128 密钥改为显式 IV，所有地址改为绝对地址
+pub fn remove_ads(
+    media_play_list: &mut MediaPlaylist,
+    playlist_url: &Url,
+    ads: &BTreeSet<usize>,
+) -> anyhow::Result<()> {
+    let mut kept = Vec::with_capacity(media_play_list.segments.len());
+    let mut active_key: Option<Key> = None;
+    let mut active_map: Option<Map> = None;
+    let mut emitted_key: Option<Key> = None;
+    let mut emitted_map: Option<Map> = None;
+    let mut removed = false;
+    for (pos, mut segment) in media_play_list.segments.drain(..).enumerate() {
+        if let Some(key) = segment.key.take() {
+            active_key = Some(key);
+        }
+        if let Some(map) = segment.map.take() {
+            active_map = Some(map);
+        }
+        if ads.contains(&pos) {
+            removed = true;
+            continue;
+        }
+        let sequence = media_play_list.media_sequence + pos as u64;
+        let mut key = active_key.clone();
+        if let Some(key) = key.as_mut() {
+            if let Some(uri) = key.uri.as_mut() {
+                *uri = playlist_url.join(uri)?.to_string();
+            }
+            if removed && key.method == KeyMethod::AES128 && key.iv.is_none() {
+                key.iv = Some(sequence_iv(sequence));
+            }
+        }
+        if key != emitted_key {
+            segment.key = Some(key.clone().unwrap_or_default());
+            emitted_key = key;
+        }
+        if active_map != emitted_map {
+            let mut map = active_map.clone().unwrap_or_default();
+            map.uri = playlist_url.join(&map.uri)?.to_string();
+            segment.map = Some(map);
+            emitted_map = active_map.clone();
+        }
+        segment.uri = playlist_url.join(&segment.uri)?.to_string();
+        kept.push(segment);
+    }
+    media_play_list.segments = kept;
+    Ok(())
+}
+
+// 分片的域名和所在目录
+fn segment_location(playlist_url: &Url, segment: &MediaSegment) -> (String, String) {
+    match playlist_url.join(&segment.uri) {
+        Ok(url) => {
+            let path = url.path();
+            let dir = &path[..path.rfind('/').unwrap_or(0)];
+            (
+                url.host_str().unwrap_or_default().to_string(),
+                dir.to_string(),
+            )
+        }
+        Err(_) => (String::new(), String::new()),
+    }
+}
+
+// 正片中出现次数最多的域名和目录
+fn most_common(locations: &[(String, String)]) -> (String, String) {
+    let mut hosts: HashMap<&str, usize> = HashMap::new();
+    let mut dirs: HashMap<&str, usize> = HashMap::new();
+    for (host, dir) in locations {
+        *hosts.entry(host).or_default() += 1;
+        *dirs.entry(dir).or_default() += 1;
+    }
+    let pick = |counts: HashMap<&str, usize>| {
+        counts
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(v, _)| v.to_string())
+            .unwrap_or_default()
+    };
+    (pick(hosts), pick(dirs))
+}
+
+pub mod cmd {
+    use tauri::command;
+
+    use super::service;
+
+    /// 返回去除广告后的播放列表，content 为空时先下载 url
+    #[command]
+    pub async fn filter_m3u8_ads(url: String, content: Option<String>) -> Result<String, String> {
+        service::filter_m3u8_ads(url, content).await
+    }
+}
+
+pub mod service {
+    use log::info;
+    use m3u8_rs::Playlist;
+    use url::Url;
+
+    use crate::download::util::download_request;
+
+    use super::{ad_filter_rule, detect_ads, remove_ads};
+
+    pub async fn filter_m3u8_ads(url: String, content: Option<String>) -> Result<String, String> {
+        let playlist_url = Url::parse(&url).map_err(|e| format!("URL 解析失败: {}", e))?;
+        let content = match content {
+            Some(content) => content.into_bytes(),
+            None => download_request(&playlist_url)
+                .await
+                .map_err(|e| format!("下载播放列表失败: {}", e))?,
+        };
+        let rule = match ad_filter_rule(&playlist_url) {
+            Some(rule) => rule,
+            None => return Ok(String::from_utf8_lossy(&content).to_string()),
+        };
+        // 主播放列表和直播播放列表原样返回
+        let mut media_play_list = match m3u8_rs::parse_playlist_res(&content) {
+            Ok(Playlist::MediaPlaylist(media_play_list)) if media_play_list.end_list => {
+                media_play_list
+            }
+            Ok(_) => return Ok(String::from_utf8_lossy(&content).to_string()),
+            Err(_) => return Err("媒体播放列表未找到".to_string()),
+        };
+        let ads = detect_ads(&media_play_list, &playlist_url, &rule);
+        if ads.is_empty() {
+            return Ok(String::from_utf8_lossy(&content).to_string());
+        }
+        info!("过滤播放列表中的广告分片, url:{}, count:{}", url, ads.len());
+        remove_ads(&mut media_play_list, &playlist_url, &ads)
+            .map_err(|e| format!("过滤广告失败: {}", e))?;
+        let mut v = Vec::new();
+        media_play_list
+            .write_to(&mut v)
+            .map_err(|e| format!("生成播放列表失败: {}", e))?;
+        Ok(String::from_utf8_lossy(&v).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use m3u8_rs::parse_media_playlist_res;
+
+    use super::*;
+
+    const PLAYLIST_URL: &str = "https://cdn.example.com/v/index.m3u8";
+
+    fn playlist(content: &str) -> MediaPlaylist {
+        parse_media_playlist_res(content.as_bytes()).unwrap()
+    }
+
+    fn host_rule(check_host: bool, check_path: bool) -> AdFilterRule {
+        AdFilterRule {
+            host: "example.com".to_string(),
+            check_host,
+            check_path,
+            ..Default::default()
+        }
+    }
+
+    // 正片中间插入一段其他域名的广告
+    const MID_ROLL: &str = "#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXT-X-MEDIA-SEQUENCE:100
+#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"
+#EXTINF:10.0,
+0.ts
+#EXTINF:10.0,
+1.ts
+#EXT-X-DISCONTINUITY
+#EXT-X-KEY:METHOD=AES-128,URI=\"https://ads.example.net/key\"
+#EXTINF:5.0,
+https://ads.example.net/ad/0.ts
+#EXTINF:5.0,
+https://ads.example.net/ad/1.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:10.0,
+2.ts
+#EXTINF:10.0,
+3.ts
+#EXT-X-ENDLIST
+";
+
+    #[test]
+    fn detect_ads_by_host() {
+        let url = Url::parse(PLAYLIST_URL).unwrap();
+        let media_play_list = playlist(MID_ROLL);
+        let ads = detect_ads(&media_play_list, &url, &host_rule(true, false));
+        assert_eq!(ads, BTreeSet::from([2, 3]));
+    }
+
+    #[test]
+    fn detect_ads_default_rule_keeps_other_hosts() {
+        // 默认不按域名和目录判断，多 CDN 的正片不会被删除
+        let url = Url::parse(PLAYLIST_URL).unwrap();
+        let media_play_list = playlist(MID_ROLL);
+        let ads = detect_ads(&media_play_list, &url, &AdFilterRule::default());
+        assert!(ads.is_empty());
+    }
+
+    #[test]
+    fn detect_ads_keeps_long_blocks() {
+        let url = Url::parse(PLAYLIST_URL).unwrap();
+        let media_play_list = playlist(MID_ROLL);
+        let rule = AdFilterRule {
+            max_ad_duration: 5.0,
+            ..host_rule(true, true)
+        };
+        assert!(detect_ads(&media_play_list, &url, &rule).is_empty());
+    }
+
+    #[test]
+    fn detect_ads_by_durations_and_patterns() {
+        let url = Url::parse(PLAYLIST_URL).unwrap();
+        let media_play_list = playlist(MID_ROLL);
+        let rule = AdFilterRule {
+            durations: vec![5.0],
+            ..host_rule(false, false)
+        };
+        assert_eq!(
+            detect_ads(&media_play_list, &url, &rule),
+            BTreeSet::from([2, 3])
+        );
+        let rule = AdFilterRule {
+            patterns: vec!["ad/1.ts".to_string()],
+            ..host_rule(false, false)
+        };
+        assert_eq!(
+            detect_ads(&media_play_list, &url, &rule),
+            BTreeSet::from([3])
+        );
+    }
+
+    #[test]
+    fn remove_ads_sets_explicit_iv_after_removal() {
+        let url = Url::parse(PLAYLIST_URL).unwrap();
+        let mut media_play_list = playlist(MID_ROLL);
+        remove_ads(&mut media_play_list, &url, &BTreeSet::from([2, 3])).unwrap();
+        let segments = &media_play_list.segments;
+        let uris: Vec<&str> = segments.iter().map(|s| s.uri.as_str()).collect();
+        assert_eq!(
+            uris,
+            [
+                "https://cdn.example.com/v/0.ts",
+                "https://cdn.example.com/v/1.ts",
+                "https://cdn.example.com/v/2.ts",
+                "https://cdn.example.com/v/3.ts",
+            ]
+        );
+        // 删除前的密钥保持原样
+        let key = segments[0].key.as_ref().unwrap();
+        assert_eq!(
+            key.uri.as_deref(),
+            Some("https://cdn.example.com/v/key.bin")
+        );
+        assert_eq!(key.iv, None);
+        assert!(segments[1].key.is_none());
+        // 广告上的密钥移到后面的分片，IV 按原来的序号显式指定
+        let key = segments[2].key.as_ref().unwrap();
+        assert_eq!(key.uri.as_deref(), Some("https://ads.example.net/key"));
+        assert_eq!(key.iv, Some(sequence_iv(104)));
+        // 每个分片的 IV 不同，所以删除之后的分片都输出密钥
+        let key = segments[3].key.as_ref().unwrap();
+        assert_eq!(key.iv, Some(sequence_iv(105)));
+    }
+
+    #[test]
+    fn remove_ads_moves_key_and_map_to_kept_segment() {
+        let url = Url::parse(PLAYLIST_URL).unwrap();
+        let mut media_play_list = playlist(
+            "#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXT-X-MEDIA-SEQUENCE:7
+#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:5.0,
+https://ads.example.net/ad/0.m4s
+#EXT-X-DISCONTINUITY
+#EXTINF:10.0,
+0.m4s
+#EXTINF:10.0,
+1.m4s
+#EXT-X-ENDLIST
+",
+        );
+        let ads = detect_ads(&media_play_list, &url, &host_rule(true, true));
+        assert_eq!(ads, BTreeSet::from([0]));
+        remove_ads(&mut media_play_list, &url, &ads).unwrap();
+        let segments = &media_play_list.segments;
+        assert_eq!(segments.len(), 2);
+        let key = segments[0].key.as_ref().unwrap();
+        assert_eq!(
+            key.uri.as_deref(),
+            Some("https://cdn.example.com/v/key.bin")
+        );
+        assert_eq!(key.iv, Some(sequence_iv(8)));
+        let map = segments[0].map.as_ref().unwrap();
+        assert_eq!(map.uri, "https://cdn.example.com/v/init.mp4");
+        // 第二个分片的 IV 也按序号计算，所以需要再次输出密钥
+        let key = segments[1].key.as_ref().unwrap();
+        assert_eq!(key.iv, Some(sequence_iv(9)));
+        assert!(segments[1].map.is_none());
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, NaiveTime};
+use lazy_static::lazy_static;
+
+use crate::conf::get_string;
+
+lazy_static! {
+    // 所有下载共享的带宽限制，包括 m3u8 分片和普通文件下载
+    pub static ref GLOBAL_BANDWIDTH: Arc<TokenBucket> = TokenBucket::new(0);
+}
+
+/// 令牌桶限速器，速率单位为字节每秒，0 表示不限速
+pub struct TokenBucket {
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    rate: u64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(BucketState {
+                rate,
+                tokens: rate as f64,
+                last: Instant::now(),
+            }),
+        })
+    }
+
+    /// 消耗 n 个字节的令牌，令牌不足时先记账再等待补足，多个分片同时下载时总速率不会超过上限
+    pub async fn consume(&self, n: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            if state.rate == 0 {
+                return;
+            }
+            let now = Instant::now();
+            let elapsed = now.duration_since(state.last).as_secs_f64();
+            state.last = now;
+            // 最多积攒一秒的令牌，避免空闲后瞬间突发
+            state.tokens = (state.tokens + elapsed * state.rate as f64).min(state.rate as f64);
+            state.tokens -= n as f64;
+            if state.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-state.tokens / state.rate as f64)
+        };
+        tokio::time::sleep(wait).await;
+    }
+
+    pub fn set_rate(&self, rate: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.rate != rate {
+            state.rate = rate;
+            state.tokens = state.tokens.min(rate as f64);
+            state.last = Instant::now();
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.state.lock().unwrap().rate
+    }
+}
+
+/// 先经过全局限速再经过任务限速
+pub async fn throttle(task_bandwidth: Option<&TokenBucket>, n: usize) {
+    GLOBAL_BANDWIDTH.consume(n).await;
+    if let Some(task_bandwidth) = task_bandwidth {
+        task_bandwidth.consume(n).await;
+    }
+}
+
+// downloadBandwidthLimit 全局限速 downloadTaskBandwidthLimit 单个任务限速，单位 KB/s，0 表示不限速
+fn kb_config(key: &str) -> u64 {
+    get_string(key).parse::<u64>().unwrap_or(0) * 1024
+}
+
+/// 当前时间命中限速时间表时使用时间表中的速率，否则使用全局限速
+pub fn global_bandwidth() -> u64 {
+    let schedule = get_string("downloadBandwidthSchedule");
+    match schedule_rate(&schedule, Local::now().time()) {
+        Some(rate) => rate * 1024,
+        None => kb_config("downloadBandwidthLimit"),
+    }
+}
+
+pub fn task_bandwidth() -> u64 {
+    kb_config("downloadTaskBandwidthLimit")
+}
+
+/// 限速时间表，格式为 "09:00-18:00=2048;22:00-02:00=0"，速率单位 KB/s，允许跨越零点
+fn schedule_rate(schedule: &str, now: NaiveTime) -> Option<u64> {
+    schedule
+        .split([';', ',', '\n'])
+        .filter_map(|rule| {
+            let (range, rate) = rule.trim().split_once('=')?;
+            let (start, end) = range.trim().split_once('-')?;
+            let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+            let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+            let rate = rate.trim().parse::<u64>().ok()?;
+            Some((start, end, rate))
+        })
+        .find(|(start, end, _)| {
+            if start <= end {
+                *start <= now && now < *end
+            } else {
+                *start <= now || now < *end
+            }
+        })
+        .map(|(_, _, rate)| rate)
+}
+
+/// 从配置读取最新的限速，下载过程中定时调用使修改和时间表立即生效
+pub fn refresh_bandwidth(task_bandwidth: Option<&TokenBucket>) {
+    GLOBAL_BANDWIDTH.set_rate(global_bandwidth());
+    if let Some(task) = task_bandwidth {
+        task.set_rate(self::task_bandwidth());
+    }
+}
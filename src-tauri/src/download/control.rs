@@ -0,0 +1,74 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use lazy_static::lazy_static;
+use tokio::sync::watch;
+
+/// 用户对单个下载任务的控制指令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadControl {
+    Running,
+    Paused,
+    Cancelled { delete_files: bool },
+    // 停止录制直播，停止当前的分片下载后合并已录制的内容
+    StopRecording,
+}
+
+lazy_static! {
+    // 下载任务 id 对应的控制通道，任务运行期间或排队中被暂停、取消时存在
+    static ref DOWNLOAD_CONTROLS: Mutex<HashMap<String, watch::Sender<DownloadControl>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// 下载开始时订阅控制通道，排队期间已经收到的暂停、取消指令会保留
+pub fn subscribe(id: &str) -> watch::Receiver<DownloadControl> {
+    DOWNLOAD_CONTROLS
+        .lock()
+        .unwrap()
+        .entry(id.to_string())
+        .or_insert_with(|| watch::channel(DownloadControl::Running).0)
+        .subscribe()
+}
+
+/// 下载结束后移除控制通道
+pub fn release(id: &str) {
+    DOWNLOAD_CONTROLS.lock().unwrap().remove(id);
+}
+
+/// 发送控制指令，返回是否有正在运行的下载接收到指令
+pub fn send(id: &str, control: DownloadControl) -> bool {
+    let mut controls = DOWNLOAD_CONTROLS.lock().unwrap();
+    match controls.get(id) {
+        Some(sender) => {
+            sender.send_replace(control);
+            sender.receiver_count() > 0
+        }
+        None => {
+            controls.insert(id.to_string(), watch::channel(control).0);
+            false
+        }
+    }
+}
+
+/// 只通知正在运行的下载，没有运行时不保留指令，返回是否有下载接收到指令
+pub fn notify(id: &str, control: DownloadControl) -> bool {
+    match DOWNLOAD_CONTROLS.lock().unwrap().get(id) {
+        Some(sender) if sender.receiver_count() > 0 => {
+            sender.send_replace(control);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// 返回需要中断下载的指令，正常运行或停止录制时返回 None
+pub fn interrupted(control: &watch::Receiver<DownloadControl>) -> Option<DownloadControl> {
+    match *control.borrow() {
+        DownloadControl::Running | DownloadControl::StopRecording => None,
+        c => Some(c),
+    }
+}
+
+/// 是否需要停止当前的分片下载，包括暂停、取消和停止录制
+pub fn stopping(control: &watch::Receiver<DownloadControl>) -> bool {
+    *control.borrow() != DownloadControl::Running
+}
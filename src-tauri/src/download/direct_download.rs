@@ -0,0 +1,603 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::TryStreamExt;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use tauri::http::{header, StatusCode};
+use tokio::{
+    fs::{remove_file, rename, File, OpenOptions},
+    io::{AsyncSeekExt, AsyncWriteExt},
+    sync::{mpsc, watch},
+    time,
+};
+use url::Url;
+
+use crate::{
+    conf::get_string,
+    orm::download_info::{service::update_download_by_id, types::DownloadInfoUpdate},
+    utils,
+};
+
+use super::{
+    bandwidth::{refresh_bandwidth, task_bandwidth, throttle, TokenBucket, GLOBAL_BANDWIDTH},
+    control::{self, DownloadControl},
+    event::publish,
+    http_client::stream_client,
+    limiter::{global_concurrency, GLOBAL_LIMITER},
+    m3u8_download::interrupted_response,
+    progress::ProgressMeter,
+    site_headers::site_headers,
+    types::{DownloadEvent, DownloadInfoContext, DownloadInfoResponse, DownloadProgress},
+    util::{retry_backoff, segment_max_attempts},
+};
+
+// 直接下载的视频格式
+const DIRECT_EXTENSIONS: [&str; 6] = ["mp4", "flv", "m4v", "mkv", "webm", "mov"];
+// 默认的连接数，以及每个连接至少下载的字节数
+const DEFAULT_CONNECTIONS: usize = 4;
+const MIN_RANGE_SIZE: u64 = 1024 * 1024;
+
+/// 每个连接负责的区间，end 为包含的结束位置，大小未知时为 u64::MAX
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ByteRangeState {
+    start: u64,
+    end: u64,
+    downloaded: u64,
+    done: bool,
+}
+
+/// 与临时文件一起保存的区间记录，重启后根据它继续下载
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RangeMap {
+    url: Url,
+    size: Option<u64>,
+    extension: String,
+    // 服务器不支持 Range 时只能使用一个连接从头下载
+    accept_ranges: bool,
+    ranges: Vec<ByteRangeState>,
+}
+
+impl RangeMap {
+    fn downloaded(&self) -> u64 {
+        self.ranges.iter().map(|r| r.downloaded).sum()
+    }
+
+    fn done_count(&self) -> usize {
+        self.ranges.iter().filter(|r| r.done).count()
+    }
+}
+
+// downloadConnections 单个视频文件同时使用的连接数
+fn connections() -> usize {
+    get_string("downloadConnections")
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_CONNECTIONS)
+}
+
+fn range_map_path(download_info_context: &DownloadInfoContext) -> PathBuf {
+    download_info_context.index_path.with_file_name(format!(
+        "{}_ranges.json",
+        download_info_context.sub_title_name
+    ))
+}
+
+fn part_path(download_info_context: &DownloadInfoContext, extension: &str) -> PathBuf {
+    download_info_context.index_path.with_file_name(format!(
+        "{}.{}.part",
+        download_info_context.sub_title_name, extension
+    ))
+}
+
+/// 已经按直接下载解析过的任务
+pub fn is_direct(download_info_context: &DownloadInfoContext) -> bool {
+    utils::exists(&range_map_path(download_info_context))
+}
+
+fn url_extension(url: &Url) -> Option<String> {
+    Path::new(url.path())
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+}
+
+/// 地址后缀为视频文件格式
+pub fn is_direct_url(url: &Url) -> bool {
+    url_extension(url).is_some_and(|extension| DIRECT_EXTENSIONS.contains(&extension.as_str()))
+}
+
+/// 没有后缀的地址根据响应类型判断是否为视频文件，只在解析播放列表失败后调用
+pub async fn is_direct_media(url: &Url) -> bool {
+    if url_extension(url).is_some() {
+        return is_direct_url(url);
+    }
+    match probe(url).await {
+        Ok((_, _, content_type)) => content_extension(&content_type).is_some(),
+        Err(_) => false,
+    }
+}
+
+fn content_extension(content_type: &str) -> Option<&'static str> {
+    match content_type {
+        t if t.starts_with("video/mp4") => Some("mp4"),
+        t if t.starts_with("video/x-flv") => Some("flv"),
+        t if t.starts_with("video/x-matroska") => Some("mkv"),
+        t if t.starts_with("video/webm") => Some("webm"),
+        t if t.starts_with("video/quicktime") => Some("mov"),
+        _ => None,
+    }
+}
+
+/// 请求第一个字节，返回 (文件大小, 是否支持 Range, 响应类型)
+async fn probe(url: &Url) -> anyhow::Result<(Option<u64>, bool, String)> {
+    let rp = stream_client()?
+        .get(url.as_str())
+        .headers(site_headers(url))
+        .header(header::RANGE, "bytes=0-0")
+        .send()
+        .await?;
+    let content_type = rp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match rp.status() {
+        StatusCode::PARTIAL_CONTENT => {
+            // Content-Range: bytes 0-0/12345
+            let size = rp
+                .headers()
+                .get(header::CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.rsplit('/').next())
+                .and_then(|v| v.parse::<u64>().ok());
+            Ok((size, size.is_some(), content_type))
+        }
+        StatusCode::OK => Ok((rp.content_length(), false, content_type)),
+        status => Err(anyhow::anyhow!("http code: {}", status)),
+    }
+}
+
+async fn load_range_map(
+    download_info_context: &DownloadInfoContext,
+) -> anyhow::Result<RangeMap, Box<dyn std::error::Error>> {
+    let v = tokio::fs::read_to_string(range_map_path(download_info_context)).await?;
+    Ok(serde_json::from_str::<RangeMap>(&v)?)
+}
+
+async fn save_range_map(
+    download_info_context: &DownloadInfoContext,
+    range_map: &RangeMap,
+) -> anyhow::Result<(), Box<dyn std::error::Error>> {
+    let v = serde_json::to_string_pretty(range_map)?;
+    tokio::fs::write(range_map_path(download_info_context), v).await?;
+    Ok(())
+}
+
+/// 探测文件大小并按连接数划分区间，创建临时文件
+pub async fn parse_source(
+    download_info_context: &mut DownloadInfoContext,
+) -> anyhow::Result<DownloadEvent, Box<dyn std::error::Error>> {
+    let url = download_info_context.url.clone();
+    let (size, accept_ranges, content_type) = probe(&url).await?;
+    let extension = url_extension(&url)
+        .filter(|e| DIRECT_EXTENSIONS.contains(&e.as_str()))
+        .or_else(|| content_extension(&content_type).map(|e| e.to_string()))
+        .unwrap_or_else(|| "mp4".to_string());
+    let ranges = match size {
+        Some(size) if accept_ranges && size > 0 => {
+            let range_size = size.div_ceil(connections() as u64).max(MIN_RANGE_SIZE);
+            (0..size)
+                .step_by(range_size as usize)
+                .map(|start| ByteRangeState {
+                    start,
+                    end: (start + range_size).min(size) - 1,
+                    downloaded: 0,
+                    done: false,
+                })
+                .collect()
+        }
+        _ => vec![ByteRangeState {
+            start: 0,
+            end: size.map_or(u64::MAX, |s| s.saturating_sub(1)),
+            downloaded: 0,
+            done: false,
+        }],
+    };
+    let range_map = RangeMap {
+        url,
+        size,
+        extension,
+        accept_ranges,
+        ranges,
+    };
+    info!(
+        "直接下载视频文件, id:{}, size:{:?}, ranges:{}",
+        download_info_context.id,
+        size,
+        range_map.ranges.len()
+    );
+    let part_file = File::create(part_path(download_info_context, &range_map.extension)).await?;
+    if let Some(size) = size.filter(|_| accept_ranges) {
+        part_file.set_len(size).await?;
+    }
+    save_range_map(download_info_context, &range_map).await?;
+
+    let count = range_map.ranges.len();
+    download_info_context.count = Some(count as i32);
+    download_info_context.download_count = 0;
+    download_info_context.downloaded_bytes = 0;
+    let download_info_update = DownloadInfoUpdate {
+        id: download_info_context.id.clone(),
+        status: Some("downloadSlice".to_string()),
+        count: Some(count as i32),
+        download_count: Some(0),
+        download_status: Some("downloading".to_string()),
+        downloaded_bytes: Some(0),
+        total_bytes: size.map(|s| s as i64),
+        ..Default::default()
+    };
+    let _ = update_download_by_id(download_info_update);
+    Ok(DownloadEvent::ParseSourceEnd(DownloadInfoResponse {
+        id: download_info_context.id.clone(),
+        status: "downloadSlice".to_string(),
+        download_count: None,
+        count: Some(count),
+        download_status: Some("downloading".to_string()),
+        reason: None,
+    }))
+}
+
+/// 每个区间使用一个连接下载到临时文件的对应位置，失败的区间从已下载的位置继续重试
+pub async fn download_slice(
+    download_info_context: &mut DownloadInfoContext,
+    control: &mut watch::Receiver<DownloadControl>,
+) -> anyhow::Result<DownloadEvent, Box<dyn std::error::Error>> {
+    let mut range_map = load_range_map(download_info_context).await?;
+    let part_path = part_path(download_info_context, &range_map.extension);
+    if !range_map.accept_ranges {
+        // 不支持 Range 时无法续传
+        for range in range_map.ranges.iter_mut() {
+            range.downloaded = 0;
+            range.done = false;
+        }
+        File::create(&part_path).await?;
+    } else if !utils::exists(&part_path) {
+        return Err(Box::from("下载临时文件不存在，请重新下载"));
+    }
+
+    let downloaded: Vec<Arc<AtomicU64>> = range_map
+        .ranges
+        .iter()
+        .map(|r| Arc::new(AtomicU64::new(r.downloaded)))
+        .collect();
+    let total = || {
+        downloaded
+            .iter()
+            .map(|d| d.load(Ordering::Relaxed))
+            .sum::<u64>()
+    };
+    let base_bytes = total();
+    let mut meter = ProgressMeter::new(base_bytes);
+    let task_bandwidth = TokenBucket::new(task_bandwidth());
+    refresh_bandwidth(Some(&task_bandwidth));
+    let active = Arc::new(AtomicUsize::new(0));
+
+    let (tx, mut rx) = mpsc::channel::<(usize, anyhow::Result<()>)>(range_map.ranges.len());
+    let mut workers = Vec::new();
+    for (i, range) in range_map.ranges.iter().enumerate().filter(|(_, r)| !r.done) {
+        let url = range_map.url.clone();
+        let part_path = part_path.clone();
+        let range = range.clone();
+        let ranged = range_map.accept_ranges;
+        let downloaded = downloaded[i].clone();
+        let task_bandwidth = task_bandwidth.clone();
+        let active = active.clone();
+        let tx = tx.clone();
+        workers.push(tokio::spawn(async move {
+            let result = download_range(
+                &url,
+                &part_path,
+                &range,
+                ranged,
+                &downloaded,
+                &task_bandwidth,
+                &active,
+            )
+            .await;
+            let _ = tx.send((i, result)).await;
+        }));
+    }
+    drop(tx);
+
+    let mut interval = time::interval(Duration::from_secs(1));
+    let mut errors = Vec::new();
+    let mut interrupted = false;
+    loop {
+        tokio::select! {
+            Ok(_) = control.changed() => {
+                if control::interrupted(control).is_some() {
+                    interrupted = true;
+                    break;
+                }
+            }
+            res = rx.recv() => {
+                match res {
+                    Some((i, Ok(()))) => range_map.ranges[i].done = true,
+                    Some((i, Err(e))) => {
+                        error!("区间下载失败: {}-{}, 原因: {}", range_map.ranges[i].start, range_map.ranges[i].end, e);
+                        errors.push(e.to_string());
+                    }
+                    None => break,
+                }
+            }
+            _ = interval.tick() => {
+                for (range, d) in range_map.ranges.iter_mut().zip(&downloaded) {
+                    range.downloaded = d.load(Ordering::Relaxed);
+                }
+                save_range_map(download_info_context, &range_map).await?;
+                refresh_bandwidth(Some(&task_bandwidth));
+                GLOBAL_LIMITER.set_limit(global_concurrency());
+                let sample = meter.sample(total() - base_bytes, range_map.size);
+                let download_count = range_map.done_count() as i32;
+                let download_info_update = DownloadInfoUpdate {
+                    id: download_info_context.id.clone(),
+                    download_count: Some(download_count),
+                    downloaded_bytes: Some(sample.downloaded_bytes as i64),
+                    total_bytes: sample.total_bytes.map(|b| b as i64),
+                    speed: Some(sample.speed as i64),
+                    average_speed: Some(sample.average_speed as i64),
+                    eta: sample.eta.map(|eta| eta as i64),
+                    ..Default::default()
+                };
+                let _ = update_download_by_id(download_info_update);
+                publish(DownloadEvent::Progress(DownloadProgress {
+                    id: download_info_context.id.clone(),
+                    download_count,
+                    failed_count: errors.len(),
+                    concurrency: range_map.ranges.len(),
+                    active: active.load(Ordering::Relaxed),
+                    global_concurrency: GLOBAL_LIMITER.limit(),
+                    bandwidth_limit: task_bandwidth.rate(),
+                    global_bandwidth_limit: GLOBAL_BANDWIDTH.rate(),
+                    downloaded_bytes: sample.downloaded_bytes,
+                    total_bytes: sample.total_bytes,
+                    speed: sample.speed,
+                    average_speed: sample.average_speed,
+                    eta: sample.eta,
+                }));
+            }
+        }
+    }
+
+    if interrupted {
+        for worker in &workers {
+            worker.abort();
+        }
+        for worker in workers {
+            let _ = worker.await;
+        }
+    }
+    for (range, d) in range_map.ranges.iter_mut().zip(&downloaded) {
+        range.downloaded = d.load(Ordering::Relaxed);
+    }
+    save_range_map(download_info_context, &range_map).await?;
+    download_info_context.download_count = range_map.done_count() as i32;
+    download_info_context.downloaded_bytes = range_map.downloaded();
+    let download_info_update = DownloadInfoUpdate {
+        id: download_info_context.id.clone(),
+        download_count: Some(download_info_context.download_count),
+        downloaded_bytes: Some(download_info_context.downloaded_bytes as i64),
+        speed: Some(0),
+        ..Default::default()
+    };
+    let _ = update_download_by_id(download_info_update);
+
+    if interrupted {
+        return Ok(interrupted_response(download_info_context));
+    }
+    if !errors.is_empty() {
+        return Err(Box::from(format!(
+            "{} 个区间多次重试后仍下载失败: {}",
+            errors.len(),
+            errors[0]
+        )));
+    }
+
+    let download_info_update = DownloadInfoUpdate {
+        id: download_info_context.id.clone(),
+        status: Some("checkSource".to_string()),
+        ..Default::default()
+    };
+    let _ = update_download_by_id(download_info_update);
+    Ok(DownloadEvent::DownloadSliceEnd(DownloadInfoResponse {
+        id: download_info_context.id.clone(),
+        status: "checkSource".to_string(),
+        download_count: Some(download_info_context.download_count),
+        ..Default::default()
+    }))
+}
+
+/// 下载一个区间，连接中断后从已写入的位置继续，没有进展的失败才计入重试次数
+///
+/// 与 HLS 分片共用全局并发限制，等待重试期间不占用并发名额
+async fn download_range(
+    url: &Url,
+    part_path: &Path,
+    range: &ByteRangeState,
+    ranged: bool,
+    downloaded: &AtomicU64,
+    task_bandwidth: &TokenBucket,
+    active: &AtomicUsize,
+) -> anyhow::Result<()> {
+    let max_attempts = segment_max_attempts();
+    let mut attempts = 0;
+    loop {
+        let before = downloaded.load(Ordering::Relaxed);
+        let result = {
+            let _global_permit = GLOBAL_LIMITER.acquire().await;
+            active.fetch_add(1, Ordering::Relaxed);
+            let result =
+                download_range_once(url, part_path, range, ranged, downloaded, task_bandwidth)
+                    .await;
+            active.fetch_sub(1, Ordering::Relaxed);
+            result
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                if downloaded.load(Ordering::Relaxed) == before {
+                    attempts += 1;
+                }
+                if attempts >= max_attempts {
+                    return Err(e);
+                }
+                // 不支持 Range 时只能从头下载
+                if !ranged {
+                    downloaded.store(0, Ordering::Relaxed);
+                }
+                time::sleep(retry_backoff(attempts.max(1))).await;
+            }
+        }
+    }
+}
+
+async fn download_range_once(
+    url: &Url,
+    part_path: &Path,
+    range: &ByteRangeState,
+    ranged: bool,
+    downloaded: &AtomicU64,
+    task_bandwidth: &TokenBucket,
+) -> anyhow::Result<()> {
+    let offset = range.start + downloaded.load(Ordering::Relaxed);
+    if range.end != u64::MAX && offset > range.end {
+        return Ok(());
+    }
+    let mut request = stream_client()?
+        .get(url.as_str())
+        .headers(site_headers(url));
+    if ranged {
+        request = request.header(header::RANGE, format!("bytes={}-{}", offset, range.end));
+    }
+    let rp = request.send().await?;
+    let status = rp.status();
+    if (ranged && status != StatusCode::PARTIAL_CONTENT) || (!ranged && status != StatusCode::OK) {
+        return Err(anyhow::anyhow!("http code: {}", status));
+    }
+    let mut file = OpenOptions::new().write(true).open(part_path).await?;
+    if !ranged {
+        file.set_len(0).await?;
+    }
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut stream = rp.bytes_stream();
+    while let Some(chunk) = stream.try_next().await? {
+        throttle(Some(task_bandwidth), chunk.len()).await;
+        // 服务器返回的数据超出区间时截断
+        let written = range.start + downloaded.load(Ordering::Relaxed);
+        let chunk = match range.end {
+            u64::MAX => &chunk[..],
+            end => &chunk[..chunk.len().min((end + 1).saturating_sub(written) as usize)],
+        };
+        file.write_all(chunk).await?;
+        downloaded.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+    }
+    file.flush().await?;
+    let expected = range.end.saturating_sub(range.start) + 1;
+    if range.end != u64::MAX && downloaded.load(Ordering::Relaxed) < expected {
+        return Err(anyhow::anyhow!(
+            "连接提前断开: {}/{}",
+            downloaded.load(Ordering::Relaxed),
+            expected
+        ));
+    }
+    Ok(())
+}
+
+/// 所有区间下载完成并且文件大小一致时进入合并，否则继续下载
+///
+/// 区间都已完成但文件大小不一致时，临时文件已被截断或修改，重置区间记录后标记失败，重试时重新下载
+pub async fn check_source(
+    download_info_context: &mut DownloadInfoContext,
+) -> anyhow::Result<DownloadEvent, Box<dyn std::error::Error>> {
+    let mut range_map = load_range_map(download_info_context).await?;
+    let part_path = part_path(download_info_context, &range_map.extension);
+    let part_len = tokio::fs::metadata(&part_path).await?.len();
+    let all_done = range_map.ranges.iter().all(|r| r.done);
+    if let Some(size) = range_map.size.filter(|size| all_done && *size != part_len) {
+        for range in range_map.ranges.iter_mut() {
+            range.downloaded = 0;
+            range.done = false;
+        }
+        let part_file = File::create(&part_path).await?;
+        if range_map.accept_ranges {
+            part_file.set_len(size).await?;
+        }
+        save_range_map(download_info_context, &range_map).await?;
+        download_info_context.download_count = 0;
+        download_info_context.downloaded_bytes = 0;
+        let download_info_update = DownloadInfoUpdate {
+            id: download_info_context.id.clone(),
+            download_count: Some(0),
+            downloaded_bytes: Some(0),
+            ..Default::default()
+        };
+        let _ = update_download_by_id(download_info_update);
+        return Err(Box::from(format!(
+            "临时文件大小不一致: {}/{}，已重置下载进度，请重新下载",
+            part_len, size
+        )));
+    }
+    let status = if all_done { "merger" } else { "downloadSlice" };
+    let download_info_update = DownloadInfoUpdate {
+        id: download_info_context.id.clone(),
+        status: Some(status.to_string()),
+        ..Default::default()
+    };
+    let _ = update_download_by_id(download_info_update);
+    Ok(DownloadEvent::CheckSourceEnd(DownloadInfoResponse {
+        id: download_info_context.id.clone(),
+        status: status.to_string(),
+        ..Default::default()
+    }))
+}
+
+/// 临时文件重命名为视频文件，删除区间记录
+pub async fn merger(
+    download_info_context: &mut DownloadInfoContext,
+) -> anyhow::Result<DownloadEvent, Box<dyn std::error::Error>> {
+    let range_map = load_range_map(download_info_context).await?;
+    let output_path = download_info_context.index_path.with_file_name(format!(
+        "{}.{}",
+        download_info_context.sub_title_name, range_map.extension
+    ));
+    rename(
+        part_path(download_info_context, &range_map.extension),
+        &output_path,
+    )
+    .await?;
+    remove_file(range_map_path(download_info_context)).await?;
+    info!("视频下载完成, path:{:?}", output_path);
+
+    let download_info_update = DownloadInfoUpdate {
+        id: download_info_context.id.clone(),
+        status: Some("downloadEnd".to_string()),
+        download_status: Some("downloadSuccess".to_string()),
+        ..Default::default()
+    };
+    let _ = update_download_by_id(download_info_update);
+    Ok(DownloadEvent::End(DownloadInfoResponse {
+        id: download_info_context.id.clone(),
+        status: "downloadEnd".to_string(),
+        download_status: Some("downloadSuccess".to_string()),
+        ..Default::default()
+    }))
+}
@@ -0,0 +1,36 @@
+use lazy_static::lazy_static;
+use log::info;
+use tauri::Emitter;
+use tokio::sync::broadcast;
+
+use crate::APP;
+
+use super::types::{DownloadEvent, DownloadMessage};
+
+// 前端监听的下载消息事件名
+pub const DOWNLOAD_EVENT: &str = "download-message";
+
+lazy_static! {
+    // 转发给 WebSocket 外部客户端的下载消息
+    static ref DOWNLOAD_EVENTS: broadcast::Sender<String> = broadcast::channel(1024).0;
+}
+
+/// 通过 Tauri 事件通知前端，同时广播给连接的 WebSocket 客户端
+pub fn publish(event: DownloadEvent) {
+    let message = DownloadMessage::new(event);
+    if let Some(app) = APP.get() {
+        if let Err(e) = app.emit(DOWNLOAD_EVENT, &message) {
+            info!("发送下载事件失败: {}", e);
+        }
+    }
+    match serde_json::to_string(&message) {
+        Ok(message) => {
+            let _ = DOWNLOAD_EVENTS.send(message);
+        }
+        Err(e) => info!("序列化下载消息失败: {}", e),
+    }
+}
+
+pub fn subscribe() -> broadcast::Receiver<String> {
+    DOWNLOAD_EVENTS.subscribe()
+}
@@ -1,15 +1,25 @@
-use crossbeam::queue::SegQueue;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use std::{
+    collections::HashSet,
+    io::ErrorKind,
     net::{TcpListener, TcpStream},
     sync::Mutex,
     thread,
+    time::Duration,
 };
+use tokio::sync::broadcast::error::TryRecvError;
 
 use log::{error, info};
-use tungstenite::{accept, handshake::HandshakeRole, Error, HandshakeError, Message, Result};
+use tungstenite::{
+    accept_hdr,
+    handshake::{
+        server::{ErrorResponse, Request, Response},
+        HandshakeRole,
+    },
+    http::StatusCode,
+    Error, HandshakeError, Message, Result,
+};
 
 #[cfg(windows)]
 use std::sync::Once;
@@ -19,7 +29,15 @@ use winapi::shared::minwindef::MAKEWORD;
 #[cfg(windows)]
 static WINSOCK_INIT: Once = Once::new();
 
-use crate::download::m3u8_download::M3u8Download;
+use crate::{
+    conf::get_string,
+    orm::download_info::service::{find_download_by_id, get_download_task_folder},
+    utils,
+};
+use crate::download::{
+    event, scheduler,
+    types::{DownloadEvent, DownloadMessage, DOWNLOAD_MESSAGE_VERSION},
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DownloadTaskInfo {
@@ -27,25 +45,77 @@ pub struct DownloadTaskInfo {
     pub movie_name: String,
     pub url: String,
     pub sub_title_name: String,
-    // parseSource 解析资源 downloadSlice 下载切片  checkSouce 检查资源完整性 merger 合并资源  downloadEnd 下载完成
+    // parseSource 解析资源 downloadSlice 下载切片 recordLive 录制直播 checkSouce 检查资源完整性 merger 合并资源  downloadEnd 下载完成
     pub status: String,
     pub download_count: i32,
     pub count: Option<i32>,
-    // wait 等待下载 downloading 下载中 downloadFail 下载失败 downloadSuccess 下载成功
+    // wait 等待下载 downloading 下载中 paused 已暂停 cancelled 已取消 downloadFail 下载失败 downloadPartial 部分分片失败 downloadSuccess 下载成功
     pub download_status: String,
     pub save_path: String,
+    // highestBandwidth 最高码率 lowestBandwidth 最低码率 resolution:1920x1080 指定分辨率 maxHeight:720 最高高度 maxBandwidth:3000000 码率上限
+    pub variant: Option<String>,
+    // 音轨、字幕选择，EXT-X-MEDIA 的 NAME 或 LANGUAGE，逗号分隔，default 表示默认，all 表示全部，为空时不下载
+    pub audio_renditions: Option<String>,
+    pub subtitle_renditions: Option<String>,
+    // 之前已下载的字节数
+    #[serde(default)]
+    pub downloaded_bytes: i64,
 }
 
 lazy_static! {
-    pub static ref DOWNLOAD_QUEUE: Mutex<SegQueue<DownloadTaskInfo>> = Mutex::new(SegQueue::new());
+    // 用户请求停止录制的直播任务 id
+    pub static ref LIVE_RECORD_STOP: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    // 每次启动生成的连接令牌，外部客户端连接时通过 ?token= 传入
+    pub static ref SERVER_TOKEN: String = utils::uuid();
 }
 
+// 应用自身页面的来源，没有 Origin 的本地程序只校验令牌
+const ALLOWED_ORIGINS: &[&str] = &[
+    "tauri://localhost",
+    "http://tauri.localhost",
+    "https://tauri.localhost",
+    // Vite 开发服务器，发布版本中同端口的其他本地页面不能通过校验
+    #[cfg(debug_assertions)]
+    "http://localhost:2420",
+];
+
+// 外部客户端使用的默认 WebSocket 端口
+const DEFAULT_SERVER_PORT: u16 = 8000;
+
+/// 外部客户端的请求，messageType 区分请求类型
 #[allow(non_snake_case)]
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct DownloadRequest {
-    pub id: String,
-    pub messageType: String,
-    pub downloadTaskInfo: Option<DownloadTaskInfo>,
+#[serde(tag = "messageType", rename_all = "camelCase")]
+pub enum DownloadRequest {
+    // 加入下载队列
+    DownloadVideo {
+        downloadTaskInfo: DownloadTaskInfo,
+    },
+    PauseDownload {
+        downloadTaskInfo: DownloadTaskInfo,
+    },
+    ResumeDownload {
+        downloadTaskInfo: DownloadTaskInfo,
+    },
+    CancelDownload {
+        downloadTaskInfo: DownloadTaskInfo,
+        // 是否删除已下载的文件
+        #[serde(default)]
+        deleteFiles: bool,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DownloadRequestMessage {
+    // 未携带版本号的旧客户端按版本 1 处理
+    #[serde(default = "default_message_version")]
+    pub version: u32,
+    #[serde(flatten)]
+    pub request: DownloadRequest,
+}
+
+fn default_message_version() -> u32 {
+    1
 }
 
 pub async fn init() {
@@ -65,15 +135,27 @@ pub async fn init() {
     // 添加小延迟确保网络栈完全初始化
     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
 
-    match TcpListener::bind("127.0.0.1:8000") {
+    // downloadServerPort 外部客户端使用的端口，0 表示不启用，应用内的下载进度通过 Tauri 事件发送
+    let port = get_string("downloadServerPort")
+        .parse::<u16>()
+        .unwrap_or(DEFAULT_SERVER_PORT);
+    if port == 0 {
+        info!("WebSocket server disabled");
+        return;
+    }
+    let addr = format!("127.0.0.1:{}", port);
+    match TcpListener::bind(&addr) {
         Ok(server) => {
-            info!("WebSocket server started on 127.0.0.1:8000");
+            info!("WebSocket server started on {}", addr);
             for stream in server.incoming() {
                 thread::spawn(move || match stream {
                     Ok(stream) => {
                         if let Err(err) = handle_client(stream) {
                             match err {
-                                Error::ConnectionClosed | Error::Protocol(_) | Error::Utf8(_) => (),
+                                Error::ConnectionClosed
+                                | Error::Protocol(_)
+                                | Error::Utf8(_)
+                                | Error::Http(_) => (),
                                 e => error!("WebSocket handler business error: {}", e),
                             }
                         }
@@ -83,11 +165,11 @@ pub async fn init() {
             }
         }
         Err(e) => {
-            error!("Failed to bind WebSocket server to 127.0.0.1:8000: {}", e);
+            error!("Failed to bind WebSocket server to {}: {}", addr, e);
             #[cfg(windows)]
             {
                 error!("This might be due to Windows network stack initialization issues.");
-                error!("Please ensure no other application is using port 8000.");
+                error!("Please ensure no other application is using port {}.", port);
             }
         }
     }
@@ -102,32 +184,43 @@ fn must_not_block<Role: HandshakeRole>(err: HandshakeError<Role>) -> Error {
 
 #[tokio::main]
 async fn handle_client(stream: TcpStream) -> Result<()> {
-    let mut socket = accept(stream).map_err(must_not_block)?;
+    let mut socket = accept_hdr(stream, check_handshake).map_err(must_not_block)?;
+    // 读取设置超时，空闲时把下载进度转发给客户端
+    socket
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_millis(200)))?;
+    let mut events = event::subscribe();
     loop {
-        match socket.read()? {
+        loop {
+            match events.try_recv() {
+                Ok(message) => socket.send(Message::text(message))?,
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+        let msg = match socket.read() {
+            Ok(msg) => msg,
+            Err(Error::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                continue
+            }
+            Err(e) => return Err(e),
+        };
+        match msg {
             msg @ Message::Text(_) | msg @ Message::Binary(_) => {
-                let request = serde_json::from_str::<DownloadRequest>(&msg.into_text()?)
-                    .expect("Resolve json error");
-                match request.messageType.as_str() {
-                    "get_download_info_by_queue" => {
-                        let queue = DOWNLOAD_QUEUE.lock().unwrap();
-                        let download_info = queue.pop();
-                        socket.send(tungstenite::Message::text(
-                            serde_json::to_string(&json!({
-                                "id": request.id.clone(),
-                                "downloadTaskInfo": download_info,
-                                "messageType": "get_download_info_by_queue"
-                            }))
-                            .expect("Failed to serialize json"),
-                        ))?
-                    }
-                    "downloadVideo" => {
-                        let download_info = request.downloadTaskInfo.unwrap();
-                        let mut m3u8_download =
-                            M3u8Download::new(&mut download_info.clone()).unwrap();
-                        m3u8_download.start_download(&mut socket).await;
+                // 无法解析的请求只回复错误，不影响连接
+                let error = match serde_json::from_str::<DownloadRequestMessage>(&msg.into_text()?) {
+                    Ok(message) if message.version > DOWNLOAD_MESSAGE_VERSION => {
+                        Some(format!("不支持的消息版本: {}", message.version))
                     }
-                    _ => {}
+                    Ok(message) => handle_request(message.request).await.err(),
+                    Err(e) => Some(format!("请求格式错误: {}", e)),
+                };
+                if let Some(reason) = error {
+                    error!("处理下载请求失败: {}", reason);
+                    let message = DownloadMessage::new(DownloadEvent::Error { reason });
+                    socket.send(Message::text(
+                        serde_json::to_string(&message).unwrap_or_default(),
+                    ))?;
                 }
             }
             Message::Ping(_) | Message::Pong(_) | Message::Close(_) | Message::Frame(_) => {}
@@ -135,8 +228,95 @@ async fn handle_client(stream: TcpStream) -> Result<()> {
     }
 }
 
+// 握手时校验来源和令牌，拒绝网页或其他程序随意连接
+#[allow(clippy::result_large_err)]
+fn check_handshake(
+    request: &Request,
+    response: Response,
+) -> std::result::Result<Response, ErrorResponse> {
+    let reject = |status: StatusCode, reason: &str| {
+        info!("拒绝 WebSocket 连接: {}", reason);
+        let mut response = ErrorResponse::new(Some(reason.to_string()));
+        *response.status_mut() = status;
+        response
+    };
+    if let Some(origin) = request.headers().get("Origin") {
+        let origin = origin.to_str().unwrap_or_default();
+        // downloadServerOrigins 额外允许的来源，逗号分隔，例如浏览器扩展
+        let allowed_origins = get_string("downloadServerOrigins");
+        let allowed = ALLOWED_ORIGINS.contains(&origin)
+            || allowed_origins
+                .split(',')
+                .map(|o| o.trim())
+                .any(|o| !o.is_empty() && o == origin);
+        if !allowed {
+            return Err(reject(StatusCode::FORBIDDEN, "不允许的来源"));
+        }
+    }
+    let token = request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="));
+    if token != Some(SERVER_TOKEN.as_str()) {
+        return Err(reject(StatusCode::UNAUTHORIZED, "令牌无效"));
+    }
+    Ok(response)
+}
+
+// 外部请求只使用任务 id，下载目录根据数据库中的记录校验，不使用客户端传入的路径和名称
+fn check_save_path(download_task_info: &DownloadTaskInfo) -> std::result::Result<(), String> {
+    if get_string("downloadSavePath").is_empty() {
+        return Err("未设置下载保存路径".to_string());
+    }
+    let download_info = find_download_by_id(&download_task_info.id)
+        .map_err(|e| format!("查询下载信息失败: {}", e))?
+        .ok_or("下载信息不存在")?;
+    // 影片名、剧集名为空或包含上级目录、绝对路径时会指向下载目录之外
+    get_download_task_folder(&download_info).ok_or(format!(
+        "下载目录无效: {}/{}",
+        download_info.movie_name, download_info.sub_title_name
+    ))?;
+    Ok(())
+}
+
+async fn handle_request(request: DownloadRequest) -> std::result::Result<(), String> {
+    let (DownloadRequest::DownloadVideo { downloadTaskInfo }
+    | DownloadRequest::PauseDownload { downloadTaskInfo }
+    | DownloadRequest::ResumeDownload { downloadTaskInfo }
+    | DownloadRequest::CancelDownload {
+        downloadTaskInfo, ..
+    }) = &request;
+    check_save_path(downloadTaskInfo)?;
+    match request {
+        // 下载任务加入持久化队列，由后台调度开始下载
+        DownloadRequest::DownloadVideo { downloadTaskInfo } => {
+            service::update_download_options(&downloadTaskInfo)
+                .map_err(|e| format!("保存下载选项失败: {}", e))?;
+            scheduler::enqueue(&downloadTaskInfo.id, 0)
+                .map_err(|e| format!("加入下载队列失败: {}", e))
+        }
+        DownloadRequest::PauseDownload { downloadTaskInfo } => {
+            service::pause_download(downloadTaskInfo.id);
+            Ok(())
+        }
+        DownloadRequest::ResumeDownload { downloadTaskInfo } => {
+            service::resume_download(downloadTaskInfo);
+            Ok(())
+        }
+        DownloadRequest::CancelDownload {
+            downloadTaskInfo,
+            deleteFiles,
+        } => service::cancel_download(downloadTaskInfo, deleteFiles).await,
+    }
+}
+
 pub mod cmd {
-    use crate::{download::file_download::service, orm::download_info::types::DownloadInfo};
+    use crate::{
+        download::{file_download::service, types::M3u8StreamList},
+        orm::download_info::types::DownloadInfo,
+    };
 
     use tauri::command;
 
@@ -149,20 +329,181 @@ pub mod cmd {
     pub async fn movie_merger(download: DownloadInfo) -> Result<DownloadInfo, String> {
         Ok(service::movie_merger(download).await?)
     }
+
+    #[command]
+    pub async fn retry_failed_segments(download: DownloadInfo) -> Result<(), String> {
+        service::retry_failed_segments(download).await
+    }
+
+    #[command]
+    pub fn stop_live_record(id: String) {
+        service::stop_live_record(id);
+    }
+
+    #[command]
+    pub fn pause_download(id: String) {
+        service::pause_download(id);
+    }
+
+    #[command]
+    pub fn resume_download(download: DownloadInfo) {
+        service::resume_download(service::download_info_to_download_task_info(download));
+    }
+
+    #[command]
+    pub async fn cancel_download(download: DownloadInfo, delete_files: bool) -> Result<(), String> {
+        service::cancel_download(
+            service::download_info_to_download_task_info(download),
+            delete_files,
+        )
+        .await
+    }
+
+    // 外部客户端连接 WebSocket 时需要携带的令牌
+    #[command]
+    pub fn get_download_server_token() -> String {
+        super::SERVER_TOKEN.clone()
+    }
+
+    #[command]
+    pub async fn get_m3u8_variants(url: String) -> Result<M3u8StreamList, String> {
+        service::get_m3u8_variants(url).await
+    }
 }
 
 pub mod service {
+    use log::{error, info};
+    use tokio::fs::remove_dir_all;
+    use url::Url;
+
     use crate::{
         conf::get_string,
-        download::{m3u8_download::merger, types::DownloadInfoContext},
-        orm::download_info::types::DownloadInfo,
+        download::{
+            control::{self, DownloadControl},
+            m3u8_download::{self, merger},
+            scheduler,
+            types::{DownloadInfoContext, M3u8StreamList},
+        },
+        orm::{
+            download_info::{
+                service::{find_download_by_id, get_download_task_folder, update_download_by_id},
+                types::{DownloadInfo, DownloadInfoUpdate},
+            },
+            download_segment::service::delete_segments,
+        },
     };
 
-    use super::{DownloadTaskInfo, DOWNLOAD_QUEUE};
+    use super::{DownloadTaskInfo, LIVE_RECORD_STOP};
 
     pub fn retry_download(download: DownloadInfo) {
-        let queue = DOWNLOAD_QUEUE.lock().unwrap();
-        queue.push(download_info_to_download_task_info(download));
+        // 正在下载的任务仍持有控制通道，释放后暂停、取消指令将无法送达
+        if scheduler::is_running(&download.id) {
+            return;
+        }
+        control::release(&download.id);
+        if let Err(e) = scheduler::enqueue(&download.id, 0) {
+            error!("加入下载队列失败: {}", e);
+        }
+    }
+
+    // 正在下载的任务停止分片下载后自行更新状态，未运行的任务移出队列并直接修改状态
+    pub fn pause_download(id: String) {
+        if !control::send(&id, DownloadControl::Paused) {
+            let _ = scheduler::dequeue(&id);
+            let download_info_update = DownloadInfoUpdate {
+                id,
+                download_status: Some("paused".to_string()),
+                ..Default::default()
+            };
+            let _ = update_download_by_id(download_info_update);
+        }
+    }
+
+    pub fn resume_download(download_task_info: DownloadTaskInfo) {
+        if scheduler::is_running(&download_task_info.id) {
+            return;
+        }
+        control::release(&download_task_info.id);
+        let download_info_update = DownloadInfoUpdate {
+            id: download_task_info.id.clone(),
+            download_status: Some("wait".to_string()),
+            ..Default::default()
+        };
+        let _ = update_download_by_id(download_info_update);
+        if let Err(e) = scheduler::enqueue(&download_task_info.id, 0) {
+            error!("加入下载队列失败: {}", e);
+        }
+    }
+
+    pub async fn cancel_download(
+        download_task_info: DownloadTaskInfo,
+        delete_files: bool,
+    ) -> Result<(), String> {
+        if control::send(&download_task_info.id, DownloadControl::Cancelled { delete_files }) {
+            return Ok(());
+        }
+        scheduler::dequeue(&download_task_info.id)
+            .map_err(|e| format!("移出下载队列失败: {}", e))?;
+        let mut download_info_update = DownloadInfoUpdate {
+            id: download_task_info.id.clone(),
+            download_status: Some("cancelled".to_string()),
+            ..Default::default()
+        };
+        if delete_files {
+            // 目录根据数据库中的记录计算，不使用客户端传入的路径
+            let download_info = find_download_by_id(&download_task_info.id)
+                .map_err(|e| format!("查询下载信息失败: {}", e))?
+                .ok_or("下载信息不存在")?;
+            let movie_path =
+                get_download_task_folder(&download_info).ok_or("下载目录无效，拒绝删除")?;
+            if movie_path.exists() {
+                remove_dir_all(&movie_path)
+                    .await
+                    .map_err(|e| format!("删除下载文件失败: {}", e))?;
+            }
+            delete_segments(&download_task_info.id)
+                .map_err(|e| format!("删除分片清单失败: {}", e))?;
+            download_info_update.status = Some("parseSource".to_string());
+            download_info_update.download_count = Some(0);
+            download_info_update.downloaded_bytes = Some(0);
+            download_info_update.total_bytes = Some(0);
+        }
+        update_download_by_id(download_info_update)
+            .map_err(|e| format!("更新下载状态失败: {}", e))?;
+        Ok(())
+    }
+
+    // 只重试多次重试后仍失败的分片，已下载的分片保留
+    pub async fn retry_failed_segments(mut download: DownloadInfo) -> Result<(), String> {
+        let mut download_task_info = download_info_to_download_task_info(download.clone());
+        let download_info_context = DownloadInfoContext::new(&mut download_task_info)
+            .map_err(|e| format!("创建视频下载对象失败: {}", e))?;
+        let count = m3u8_download::reset_failed_segments(&download_info_context)
+            .await
+            .map_err(|e| format!("重置失败分片失败: {}", e))?;
+        info!("重试失败分片, id:{}, count:{}", download.id, count);
+        download.status = "downloadSlice".to_string();
+        download.download_status = "downloading".to_string();
+        let download_info_update = DownloadInfoUpdate {
+            id: download.id.clone(),
+            status: Some(download.status.clone()),
+            download_status: Some(download.download_status.clone()),
+            ..Default::default()
+        };
+        let _ = update_download_by_id(download_info_update);
+        retry_download(download);
+        Ok(())
+    }
+
+    // 正在录制的任务立即停止等待和分片下载，未运行的任务在下次开始录制时停止
+    pub fn stop_live_record(id: String) {
+        LIVE_RECORD_STOP.lock().unwrap().insert(id.clone());
+        control::notify(&id, DownloadControl::StopRecording);
+    }
+
+    // 录制循环在每次刷新播放列表前检查，取出后即清除
+    pub fn take_live_record_stop(id: &str) -> bool {
+        LIVE_RECORD_STOP.lock().unwrap().remove(id)
     }
 
     pub async fn movie_merger(mut download: DownloadInfo) -> Result<DownloadInfo, String> {
@@ -170,17 +511,44 @@ pub mod service {
         let mut download_info_context: DownloadInfoContext =
             DownloadInfoContext::new(&mut download_task_info)
                 .map_err(|e| format!("创建视频下载对象失败: {}", e))?;
-        let result = merger(&mut download_info_context).await;
-        match result {
-            Ok(_) => {
-                download.download_status = "downloadSuccess".to_string();
-                download.status = "downloadEnd".to_string();
-                return Ok(download);
+        merger(&mut download_info_context)
+            .await
+            .map_err(|e| format!("合并视频失败: {}", e))?;
+        download.download_status = "downloadSuccess".to_string();
+        download.status = "downloadEnd".to_string();
+        Ok(download)
+    }
+
+    /// 外部客户端加入队列时指定的媒体流和音轨字幕，解析资源后再修改不会生效，只在解析之前保存
+    pub fn update_download_options(download_task_info: &DownloadTaskInfo) -> anyhow::Result<()> {
+        if download_task_info.variant.is_none()
+            && download_task_info.audio_renditions.is_none()
+            && download_task_info.subtitle_renditions.is_none()
+        {
+            return Ok(());
+        }
+        match find_download_by_id(&download_task_info.id)? {
+            Some(download_info) if download_info.status == "parseSource" => {
+                update_download_by_id(DownloadInfoUpdate {
+                    id: download_info.id,
+                    variant: download_task_info.variant.clone(),
+                    audio_renditions: download_task_info.audio_renditions.clone(),
+                    subtitle_renditions: download_task_info.subtitle_renditions.clone(),
+                    ..Default::default()
+                })?;
+                Ok(())
             }
-            Err(_) => Ok(download),
+            _ => Ok(()),
         }
     }
 
+    pub async fn get_m3u8_variants(url: String) -> Result<M3u8StreamList, String> {
+        let url = Url::parse(&url).map_err(|e| format!("URL 解析失败: {}", e))?;
+        m3u8_download::get_m3u8_variants(&url)
+            .await
+            .map_err(|e| format!("获取媒体流列表失败: {}", e))
+    }
+
     pub fn download_info_to_download_task_info(download_info: DownloadInfo) -> DownloadTaskInfo {
         DownloadTaskInfo {
             id: download_info.id,
@@ -192,6 +560,10 @@ pub mod service {
             count: Some(download_info.count),
             download_status: download_info.download_status,
             save_path: get_string("downloadSavePath"),
+            variant: download_info.variant,
+            audio_renditions: download_info.audio_renditions,
+            subtitle_renditions: download_info.subtitle_renditions,
+            downloaded_bytes: download_info.downloaded_bytes,
         }
     }
 }
@@ -0,0 +1,266 @@
+use std::{sync::Mutex, time::Duration};
+
+use lazy_static::lazy_static;
+use tauri_plugin_http::reqwest::{Client, Proxy};
+
+use crate::{conf::get_string, utils::choose_user_agent};
+
+// 未配置时的请求超时，单位秒
+const DEFAULT_TIMEOUT: u64 = 10;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// 连接池参数，同一个域名的分片复用连接，避免每个分片重新建立连接和 TLS 握手
+// 空闲连接上限小于并发数时，每批分片结束后多出的连接被关闭，下一批重新握手，
+// 取默认全局并发数 16 的两倍，调大并发或同时请求播放列表、密钥时仍能复用
+const POOL_MAX_IDLE_PER_HOST: usize = 32;
+// 与 reqwest 的默认值一致，覆盖直播刷新播放列表和调度切换任务的间隔，
+// 服务器先关闭的连接在取出时会被丢弃，不会用于请求
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+// 空闲期间发送保活包，避免 NAT 和代理提前回收连接
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    // 下载引擎共享的客户端，所有任务共用连接池，配置变化后重新创建
+    static ref CLIENTS: Mutex<Option<(ClientConfig, DownloadClients)>> = Mutex::new(None);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ClientConfig {
+    proxy: Option<String>,
+    timeout: u64,
+    user_agent: String,
+}
+
+#[derive(Clone)]
+struct DownloadClients {
+    request: Client,
+    stream: Client,
+}
+
+impl ClientConfig {
+    fn load() -> Self {
+        let protocol = get_string("proxyProtocol").to_lowercase();
+        let proxy = match protocol.as_str() {
+            "http" => Some("http"),
+            // 代理端解析域名，避免本地 DNS 污染
+            "socks" | "socks5" => Some("socks5h"),
+            _ => None,
+        }
+        .map(|scheme| {
+            format!(
+                "{}://{}:{}",
+                scheme,
+                get_string("proxyServer"),
+                get_string("proxyPort")
+            )
+        });
+        let timeout = get_string("downloadTimeout")
+            .parse::<u64>()
+            .ok()
+            .filter(|t| *t > 0)
+            .unwrap_or(DEFAULT_TIMEOUT);
+        Self {
+            proxy,
+            timeout,
+            user_agent: get_string("downloadUserAgent"),
+        }
+    }
+
+    fn build(&self) -> anyhow::Result<DownloadClients> {
+        // 配置为 pc 或 mobile 时随机选择一个，配置不变时保持同一个
+        let user_agent = choose_user_agent(&self.user_agent).to_string();
+        let builder = || -> anyhow::Result<_> {
+            let builder = Client::builder()
+                .user_agent(user_agent.clone())
+                .connect_timeout(CONNECT_TIMEOUT)
+                .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+                .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+                .tcp_keepalive(TCP_KEEPALIVE)
+                .tcp_nodelay(true)
+                // 服务器支持 HTTP/2 时多个分片共用一个连接
+                .http2_adaptive_window(true);
+            Ok(match &self.proxy {
+                Some(proxy) => builder.proxy(
+                    Proxy::all(proxy)
+                        .map_err(|e| anyhow::anyhow!("代理地址无效 {}: {}", proxy, e))?,
+                ),
+                None => builder.no_proxy(),
+            })
+        };
+        let timeout = Duration::from_secs(self.timeout);
+        Ok(DownloadClients {
+            request: builder()?.timeout(timeout).build()?,
+            // 大文件下载不限制总时长，只限制两次读取之间的间隔
+            stream: builder()?.read_timeout(timeout).build()?,
+        })
+    }
+}
+
+fn clients() -> anyhow::Result<DownloadClients> {
+    let config = ClientConfig::load();
+    let mut clients = CLIENTS.lock().unwrap();
+    match clients.as_ref() {
+        Some((current, c)) if *current == config => Ok(c.clone()),
+        _ => {
+            let c = config.build()?;
+            *clients = Some((config, c.clone()));
+            Ok(c)
+        }
+    }
+}
+
+/// 播放列表和密钥请求使用的客户端，带总超时
+pub fn client() -> anyhow::Result<Client> {
+    Ok(clients()?.request)
+}
+
+/// 分片和视频文件下载使用的客户端，只有连接和读取超时
+pub fn stream_client() -> anyhow::Result<Client> {
+    Ok(clients()?.stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Instant,
+    };
+
+    use futures::{stream, StreamExt};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        time,
+    };
+
+    use super::*;
+
+    const SEGMENT_COUNT: usize = 200;
+    const SEGMENT_SIZE: usize = 256 * 1024;
+    // 与默认的单个任务并发数一致
+    const CONCURRENCY: usize = 6;
+    // 每个新连接先等待一段时间，模拟访问远程 CDN 时 TCP 和 TLS 握手的往返
+    const HANDSHAKE_DELAY: Duration = Duration::from_millis(30);
+
+    fn fixture_playlist() -> String {
+        let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:4\n");
+        for i in 0..SEGMENT_COUNT {
+            playlist.push_str(&format!("#EXTINF:4.0,\nseg{}.ts\n", i));
+        }
+        playlist.push_str("#EXT-X-ENDLIST\n");
+        playlist
+    }
+
+    async fn serve_connection(mut socket: TcpStream, playlist: Arc<String>) -> std::io::Result<()> {
+        time::sleep(HANDSHAKE_DELAY).await;
+        let segment = vec![0x47u8; SEGMENT_SIZE];
+        let mut buf = Vec::new();
+        let mut read = [0u8; 4096];
+        loop {
+            let end = loop {
+                if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break end;
+                }
+                let n = socket.read(&mut read).await?;
+                if n == 0 {
+                    return Ok(());
+                }
+                buf.extend_from_slice(&read[..n]);
+            };
+            let request = String::from_utf8_lossy(&buf[..end]).to_string();
+            buf.drain(..end + 4);
+            let body = match request.split_whitespace().nth(1) {
+                Some("/index.m3u8") => playlist.as_bytes(),
+                _ => segment.as_slice(),
+            };
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: keep-alive\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(head.as_bytes()).await?;
+            socket.write_all(body).await?;
+        }
+    }
+
+    /// 启动本地的静态 HLS 服务，返回播放列表地址和已建立的连接数
+    async fn start_fixture_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        let playlist = Arc::new(fixture_playlist());
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(serve_connection(socket, playlist.clone()));
+            }
+        });
+        (format!("http://{}/index.m3u8", addr), connections)
+    }
+
+    /// 解析播放列表后按固定并发下载全部分片，返回耗时和新建的连接数
+    async fn download_fixture(client: impl Fn() -> Client) -> (Duration, usize) {
+        let (playlist_url, connections) = start_fixture_server().await;
+        let started = Instant::now();
+        let content = client()
+            .get(&playlist_url)
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let playlist = m3u8_rs::parse_media_playlist_res(&content).unwrap();
+        let base = url::Url::parse(&playlist_url).unwrap();
+        let sizes: Vec<usize> = stream::iter(playlist.segments)
+            .map(|segment| {
+                let client = client();
+                let url = base.join(&segment.uri).unwrap();
+                async move {
+                    let resp = client.get(url.as_str()).send().await.unwrap();
+                    resp.bytes().await.unwrap().len()
+                }
+            })
+            .buffer_unordered(CONCURRENCY)
+            .collect()
+            .await;
+        assert_eq!(sizes.len(), SEGMENT_COUNT);
+        assert!(sizes.iter().all(|size| *size == SEGMENT_SIZE));
+        (started.elapsed(), connections.load(Ordering::Relaxed))
+    }
+
+    fn report(name: &str, (elapsed, connections): (Duration, usize)) {
+        let megabytes = (SEGMENT_COUNT * SEGMENT_SIZE) as f64 / 1024.0 / 1024.0;
+        println!(
+            "{:<28} {:>7.2}s {:>8.1} MB/s {:>4} connections",
+            name,
+            elapsed.as_secs_f64(),
+            megabytes / elapsed.as_secs_f64(),
+            connections
+        );
+    }
+
+    // cargo test download_throughput -- --ignored --nocapture
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn download_throughput() {
+        let config = ClientConfig {
+            proxy: None,
+            timeout: DEFAULT_TIMEOUT,
+            user_agent: String::new(),
+        };
+        let per_segment = download_fixture(Client::new).await;
+        report("client per segment", per_segment);
+        let pool_of_one = Client::builder().pool_max_idle_per_host(1).build().unwrap();
+        let single_idle = download_fixture(|| pool_of_one.clone()).await;
+        report("shared, 1 idle per host", single_idle);
+        let shared = config.build().unwrap().stream;
+        let tuned = download_fixture(|| shared.clone()).await;
+        report("shared, tuned pool", tuned);
+        // 空闲连接数不小于并发数时，分片之间不再重新建立连接
+        assert!(tuned.1 < single_idle.1);
+        assert!(tuned.1 < per_segment.1);
+    }
+}
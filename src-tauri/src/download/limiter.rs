@@ -0,0 +1,104 @@
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+use tokio::sync::Notify;
+
+use crate::conf::get_string;
+
+// 默认的全局并发数和单个任务的并发数
+const DEFAULT_GLOBAL_CONCURRENCY: usize = 16;
+const DEFAULT_TASK_CONCURRENCY: usize = 6;
+
+lazy_static! {
+    // 所有下载任务共享的分片并发限制
+    pub static ref GLOBAL_LIMITER: Arc<ConcurrencyLimiter> =
+        ConcurrencyLimiter::new(DEFAULT_GLOBAL_CONCURRENCY);
+}
+
+/// 可在下载过程中调整上限的并发限制器，调小上限时已经在下载的分片不受影响
+pub struct ConcurrencyLimiter {
+    state: Mutex<LimiterState>,
+    notify: Notify,
+}
+
+struct LimiterState {
+    limit: usize,
+    active: usize,
+}
+
+pub struct LimiterPermit {
+    limiter: Arc<ConcurrencyLimiter>,
+}
+
+impl Drop for LimiterPermit {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().active -= 1;
+        self.limiter.notify.notify_waiters();
+    }
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(LimiterState {
+                limit: limit.max(1),
+                active: 0,
+            }),
+            notify: Notify::new(),
+        })
+    }
+
+    pub async fn acquire(self: &Arc<Self>) -> LimiterPermit {
+        loop {
+            // 先注册等待再检查，避免检查后、等待前的释放被漏掉
+            let notified = self.notify.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.active < state.limit {
+                    state.active += 1;
+                    return LimiterPermit {
+                        limiter: self.clone(),
+                    };
+                }
+            }
+            notified.await;
+        }
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        let mut state = self.state.lock().unwrap();
+        let limit = limit.max(1);
+        if state.limit != limit {
+            state.limit = limit;
+            drop(state);
+            self.notify.notify_waiters();
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    pub fn active(&self) -> usize {
+        self.state.lock().unwrap().active
+    }
+}
+
+// downloadGlobalConcurrency 全局并发数 downloadTaskConcurrency 单个任务并发数
+pub fn global_concurrency() -> usize {
+    get_string("downloadGlobalConcurrency")
+        .parse()
+        .unwrap_or(DEFAULT_GLOBAL_CONCURRENCY)
+}
+
+pub fn task_concurrency() -> usize {
+    get_string("downloadTaskConcurrency")
+        .parse()
+        .unwrap_or(DEFAULT_TASK_CONCURRENCY)
+}
+
+/// 从配置读取最新的并发数，下载过程中定时调用使修改立即生效
+pub fn refresh_limits(task_limiter: &ConcurrencyLimiter) {
+    GLOBAL_LIMITER.set_limit(global_concurrency());
+    task_limiter.set_limit(task_concurrency());
+}
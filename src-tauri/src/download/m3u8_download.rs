@@ -1,191 +1,486 @@
+use chrono::Local;
 use crossbeam::queue::SegQueue;
 use log::{error, info};
-use m3u8_rs::{MediaPlaylist, Playlist};
-use moka::sync::Cache;
-use serde_json::json;
+use m3u8_rs::{AlternativeMediaType, KeyMethod, MediaPlaylist, MediaPlaylistType, Playlist};
 use std::{
+    collections::{HashMap, HashSet},
     fs::create_dir_all,
-    net::TcpStream,
+    io::ErrorKind,
     path::{Path, PathBuf},
     process::Command,
     sync::{
         atomic::{AtomicI32, Ordering},
         Arc,
     },
-    thread,
     time::Duration,
 };
-use tokio::io::AsyncBufReadExt;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt};
 use tokio::{
     fs::{remove_dir_all, remove_file, File, OpenOptions},
     io::{AsyncWriteExt, BufReader, BufWriter},
-    sync::{mpsc, Semaphore},
+    sync::{mpsc, watch},
     time,
 };
-use tungstenite::WebSocket;
+use url::Url;
 
 use crate::{
-    orm::download_info::{service::update_download_by_id, types::DownloadInfoUpdate},
+    conf::get_string,
+    orm::{
+        download_info::{
+            service::{find_download_by_id, get_download_task_folder, update_download_by_id},
+            types::DownloadInfoUpdate,
+        },
+        download_segment::{
+            self,
+            service::{delete_segments, get_segments, save_segments},
+            types::DownloadSegment,
+        },
+    },
     utils,
 };
 
 use super::{
-    file_download::DownloadTaskInfo,
-    m3u8_encrypt_key::M3u8EncryptKey,
+    ad_filter::{ad_filter_rule, detect_ads},
+    bandwidth::{refresh_bandwidth, task_bandwidth, TokenBucket, GLOBAL_BANDWIDTH},
+    control::{self, DownloadControl},
+    direct_download,
+    event::publish,
+    file_download::{service::take_live_record_stop, DownloadTaskInfo},
+    limiter::{refresh_limits, task_concurrency, ConcurrencyLimiter, GLOBAL_LIMITER},
+    m3u8_encrypt_key::{key_url, sequence_iv, M3u8EncryptKey},
+    progress::{estimate_total_bytes, ProgressMeter},
+    sample_aes,
     types::{
-        parse_operation_name, DownloadInfoContext, DownloadInfoDetail, DownloadInfoQueueDetail,
-        DownloadInfoResponse, DownloadOperation, DownloadSourceInfo,
+        parse_operation_name, DownloadEvent, DownloadInfoContext, DownloadInfoDetail,
+        DownloadInfoQueueDetail, DownloadInfoResponse, DownloadOperation, DownloadProgress,
+        DownloadSourceInfo, LiveRecordInfo, M3u8Rendition, M3u8StreamList, M3u8Variant,
+        MediaContainer, MediaTrack, MediaTrackType, PartialDownload, SegmentResult,
     },
-    util::{download_request, download_ts},
+    util::{download_request, download_segment, retry_backoff, segment_max_attempts},
 };
 
+// fMP4 初始化分片文件名前缀，合并时据此分组
+const INIT_SEGMENT_PREFIX: &str = "init_";
+// 分片清单中的状态
+const SEGMENT_SUCCESS: &str = "success";
+const SEGMENT_FAILED: &str = "failed";
+
 pub struct M3u8Download {
     pub download_info_context: DownloadInfoContext,
-    pub cache: Cache<String, i32>,
-    pub uq_key: String,
 }
 
 impl M3u8Download {
     pub fn new(download_info: &mut DownloadTaskInfo) -> Result<Self, Box<dyn std::error::Error>> {
         let download_info_context = DownloadInfoContext::new(download_info)?;
         Ok(M3u8Download {
-            download_info_context: download_info_context.clone(),
-            cache: Cache::builder()
-                .max_capacity(1024)
-                .time_to_live(Duration::from_secs(1 * 60 * 60))
-                .build(),
-            uq_key: format!(
-                "{}_{}",
-                download_info_context.url.as_str(),
-                download_info_context.movie_name.clone()
-            ),
+            download_info_context,
         })
     }
 
-    pub async fn start_download(&mut self, socket: &mut WebSocket<TcpStream>) {
-        let mut result;
-
+    pub async fn start_download(&mut self) {
         let mut operation = parse_operation_name(&self.download_info_context.status[..]);
+        let mut control = control::subscribe(&self.download_info_context.id);
 
         loop {
-            match operation {
+            // 每个阶段结束后检查是否被暂停或取消，排队期间收到的指令在开始前生效
+            if operation != DownloadOperation::DownloadEnd {
+                if let Some(c) = control::interrupted(&control) {
+                    self.interrupt(c).await;
+                    break;
+                }
+            }
+            let result = match operation {
                 DownloadOperation::ParseSource => {
-                    result = parse_source(&mut self.download_info_context).await;
+                    parse_source(&mut self.download_info_context).await
                 }
                 DownloadOperation::DownloadSlice => {
-                    result = download_slice(&mut self.download_info_context, socket).await;
+                    download_slice(&mut self.download_info_context, &mut control).await
                 }
-                DownloadOperation::CheckSource => {
-                    result = check_source(&mut self.download_info_context).await;
+                DownloadOperation::RecordLive => {
+                    record_live(&mut self.download_info_context, &mut control).await
                 }
-                DownloadOperation::Merger => {
-                    result = merger(&mut self.download_info_context).await;
-                }
-                DownloadOperation::UnsupportedOperation => {
-                    result = Err(Box::from(format!(
-                        "{}不支持的操作",
-                        self.download_info_context.status
-                    )));
+                DownloadOperation::CheckSource => {
+                    check_source(&mut self.download_info_context).await
                 }
+                DownloadOperation::Merger => merger(&mut self.download_info_context).await,
+                DownloadOperation::UnsupportedOperation => Err(Box::from(format!(
+                    "{}不支持的操作",
+                    self.download_info_context.status
+                ))),
                 DownloadOperation::DownloadEnd => break,
-            }
-            let mut out_limit_num = false;
-            // 程序报错直接修改任务状态为失败
-            let rs_sucess = result.is_ok();
-            if rs_sucess {
-                let rl = result.unwrap();
-                if let Ok(r) = serde_json::to_string(&rl) {
-                    let m = socket.send(tungstenite::Message::text(r));
-                    if m.is_err() {
-                        info!("发送消息失败");
+            };
+            // 程序报错直接修改任务状态为失败，部分分片失败时单独标记，等待用户处理
+            match result {
+                Ok(event) => {
+                    if let Some(response) = event.response() {
+                        operation = parse_operation_name(&response.status[..]);
+                        self.download_info_context.status = response.status.clone();
                     }
+                    publish(event);
                 }
-                operation = parse_operation_name(&rl.status[..]);
-                if operation == DownloadOperation::DownloadSlice {
-                    let entry = self.cache.entry(self.uq_key.clone()).or_insert(0);
-                    let x = entry.value() + 1;
-                    self.cache.insert(self.uq_key.clone(), x);
-                    if x > 5 {
-                        info!("下载影片：{}, 超过重试阈值", self.uq_key.clone());
-                        out_limit_num = true;
-                    }
+                Err(e) => {
+                    let reason = e.to_string();
+                    let download_status = if e.is::<PartialDownload>() {
+                        "downloadPartial"
+                    } else {
+                        "downloadFail"
+                    };
+                    error!("下载m3u8失败，失败原因:{}", reason);
+                    let download_info_update = DownloadInfoUpdate {
+                        id: self.download_info_context.id.clone(),
+                        status: Some(self.download_info_context.status.clone()),
+                        download_status: Some(download_status.to_string()),
+                        ..Default::default()
+                    };
+                    let _ = update_download_by_id(download_info_update);
+                    publish(DownloadEvent::End(DownloadInfoResponse {
+                        id: self.download_info_context.id.clone(),
+                        status: self.download_info_context.status.clone(),
+                        download_status: Some(download_status.to_string()),
+                        reason: Some(reason),
+                        ..Default::default()
+                    }));
+                    break;
                 }
-            } else {
-                error!("下载m3u8失败，失败原因:{}", result.unwrap_err());
             }
-            if !rs_sucess || out_limit_num {
-                let download_info_update = DownloadInfoUpdate {
-                    id: self.download_info_context.id.clone(),
-                    status: Some(self.download_info_context.status.clone()),
-                    download_status: Some("downloadFail".to_string()),
-                    ..Default::default()
-                };
-                let _ = update_download_by_id(download_info_update);
-                let m = socket.send(tungstenite::Message::text(
-                    serde_json::to_string(&json!({
-                        "id": self.download_info_context.id,
-                        "status": self.download_info_context.status,
-                        "download_status": "downloadFail",
-                        "mes_type": "end"
-                    }))
-                    .unwrap(),
-                ));
-                if m.is_err() {
-                    info!("发送消息失败");
+        }
+        control::release(&self.download_info_context.id);
+    }
+
+    /// 暂停时保留已下载的分片，取消时可以删除整个任务目录，下次从头解析
+    async fn interrupt(&mut self, control: DownloadControl) {
+        let context = &mut self.download_info_context;
+        let mut download_info_update = DownloadInfoUpdate {
+            id: context.id.clone(),
+            status: Some(context.status.clone()),
+            download_count: Some(context.download_count),
+            ..Default::default()
+        };
+        let download_status = match control {
+            DownloadControl::Cancelled { delete_files } => {
+                if delete_files {
+                    // 目录根据数据库中的记录计算，名称为空时拒绝删除
+                    let movie_path = find_download_by_id(&context.id)
+                        .ok()
+                        .flatten()
+                        .and_then(|download_info| get_download_task_folder(&download_info));
+                    match movie_path {
+                        Some(movie_path) => {
+                            if let Err(e) = remove_dir_all(movie_path).await {
+                                error!("删除下载文件失败: {}", e);
+                            }
+                        }
+                        None => error!("下载目录无效，拒绝删除, id:{}", context.id),
+                    }
+                    // 运行中的任务可能在取消前又写入了分片记录
+                    if let Err(e) = delete_segments(&context.id) {
+                        error!("删除分片清单失败: {}", e);
+                    }
+                    context.status = "parseSource".to_string();
+                    context.download_count = 0;
+                    context.downloaded_bytes = 0;
+                    download_info_update.status = Some(context.status.clone());
+                    download_info_update.download_count = Some(0);
+                    download_info_update.downloaded_bytes = Some(0);
+                    download_info_update.total_bytes = Some(0);
                 }
-                break;
+                "cancelled"
             }
-        }
+            _ => "paused",
+        };
+        info!("下载任务{}, id:{}", download_status, context.id);
+        download_info_update.download_status = Some(download_status.to_string());
+        let _ = update_download_by_id(download_info_update);
+        publish(DownloadEvent::End(DownloadInfoResponse {
+            id: context.id.clone(),
+            status: context.status.clone(),
+            download_count: Some(context.download_count),
+            download_status: Some(download_status.to_string()),
+            ..Default::default()
+        }));
     }
 }
 
 async fn parse_source(
     download_info_context: &mut DownloadInfoContext,
-) -> anyhow::Result<DownloadInfoResponse, Box<dyn std::error::Error>> {
-    let media_play_list = parse_m3u8(download_info_context).await?;
-    let count = media_play_list.segments.len();
+) -> anyhow::Result<DownloadEvent, Box<dyn std::error::Error>> {
+    // 直接链接的视频文件不经过 m3u8 解析
+    if direct_download::is_direct_url(&download_info_context.url) {
+        return direct_download::parse_source(download_info_context).await;
+    }
+    let source_url = download_info_context.url.clone();
+    // 错误在 await 之前转为字符串，下载任务需要在多线程运行时中执行
+    let parsed = parse_m3u8(download_info_context)
+        .await
+        .map_err(|e| e.to_string());
+    let (media_play_list, tracks) = match parsed {
+        Ok(parsed) => parsed,
+        // 没有后缀的地址不是播放列表时，再判断是否为视频文件
+        Err(reason) => {
+            if direct_download::is_direct_media(&source_url).await {
+                download_info_context.url = source_url;
+                return direct_download::parse_source(download_info_context).await;
+            }
+            return Err(Box::from(reason));
+        }
+    };
     let mut download_source_info = DownloadSourceInfo::new();
     download_source_info.id = download_info_context.id.clone();
+    download_source_info.bandwidth = download_info_context.bandwidth;
+    if media_play_list.segments.iter().any(|s| s.map.is_some()) {
+        download_source_info.container = MediaContainer::Fmp4;
+    }
+    // 没有 EXT-X-ENDLIST 的直播或 EVENT 播放列表进入录制模式
+    let live =
+        !media_play_list.end_list && media_play_list.playlist_type != Some(MediaPlaylistType::Vod);
+    let mut current_map = None;
+    let sequence = media_play_list.media_sequence;
+    if live {
+        download_source_info.live = Some(LiveRecordInfo {
+            media_url: download_info_context.url.clone(),
+            base_sequence: sequence,
+            next_sequence: sequence,
+            target_duration: media_play_list.target_duration,
+            start_time: Local::now().timestamp(),
+            current_map: None,
+        });
+    }
+
+    // 广告只在点播的主媒体流中过滤，独立的音轨无法对应删除
+    match ad_filter_rule(&download_info_context.url) {
+        Some(rule) if !live && tracks.is_empty() => {
+            download_source_info.ad_segments =
+                detect_ads(&media_play_list, &download_info_context.url, &rule);
+            if !download_source_info.ad_segments.is_empty() {
+                info!(
+                    "过滤广告分片, id:{}, count:{}",
+                    download_info_context.id,
+                    download_source_info.ad_segments.len()
+                );
+            }
+        }
+        _ => {}
+    }
+    download_source_info.duration = media_play_list
+        .segments
+        .iter()
+        .enumerate()
+        .filter(|(pos, _)| !download_source_info.ad_segments.contains(pos))
+        .map(|(_, s)| s.duration as f64)
+        .sum();
 
     create_dir_all(&download_info_context.ts_path)?;
 
     let index = &download_info_context.index_path;
     let index_file = utils::async_create_file(index).await?;
+    let mut index_file = BufWriter::new(index_file);
+    let mut count = append_segments(
+        download_info_context,
+        &media_play_list,
+        &mut download_source_info,
+        None,
+        (sequence, sequence),
+        &mut current_map,
+        &mut index_file,
+    )
+    .await?;
+    index_file.flush().await?;
+
+    // 直播录制只跟踪主媒体流
+    if live && !tracks.is_empty() {
+        info!(
+            "直播录制暂不支持独立的音轨和字幕, id:{}",
+            download_info_context.id
+        );
+    }
+    for mut track in tracks.into_iter().filter(|_| !live) {
+        let track_play_list = parse_media_play_list(&track.media_url).await?;
+        if track_play_list.segments.iter().any(|s| s.map.is_some()) {
+            track.container = MediaContainer::Fmp4;
+        }
+        create_dir_all(&track.ts_path)?;
+        let track_index_file = utils::async_create_file(&track.index_path).await?;
+        let mut track_index_file = BufWriter::new(track_index_file);
+        let sequence = track_play_list.media_sequence;
+        count += append_segments(
+            download_info_context,
+            &track_play_list,
+            &mut download_source_info,
+            Some(&track),
+            (sequence, sequence),
+            &mut None,
+            &mut track_index_file,
+        )
+        .await?;
+        track_index_file.flush().await?;
+        download_source_info.tracks.push(track);
+    }
+    if let Some(live) = download_source_info.live.as_mut() {
+        live.next_sequence = sequence + media_play_list.segments.len() as u64;
+        live.current_map = current_map;
+    }
+    save_source_info(download_info_context, &download_source_info).await?;
 
-    let mut download_list: Vec<DownloadInfoDetail> = Vec::new();
+    let status = if live { "recordLive" } else { "downloadSlice" };
+    let download_info_update = DownloadInfoUpdate {
+        id: download_info_context.id.clone(),
+        status: Some(status.to_string()),
+        count: Some(count as i32),
+        download_status: Some("downloading".to_string()),
+        ..Default::default()
+    };
+    let _ = update_download_by_id(download_info_update);
+    Ok(DownloadEvent::ParseSourceEnd(DownloadInfoResponse {
+        id: download_info_context.id.clone(),
+        status: status.to_string(),
+        download_count: None,
+        count: Some(count),
+        download_status: Some("downloading".to_string()),
+        reason: None,
+    }))
+}
 
-    let mut index_file = BufWriter::new(index_file);
-    for (i, segment) in media_play_list.segments.iter().enumerate() {
-        let file_name = download_info_context
-            .ts_path
-            .join(Path::new(&segment.uri).file_name().unwrap());
-        let file_name_str = utils::get_path_name(&file_name);
-        let json_success_path = &download_info_context.json_success_path;
-        if utils::exists(&PathBuf::from(json_success_path)) {
-            if let Ok(success_v) = &std::fs::read_to_string(json_success_path) {
-                if success_v.contains(&file_name_str) {
-                    continue;
+/// 把媒体播放列表中序列号不小于 start_sequence 的分片追加到下载清单和索引文件，返回追加的文件数
+///
+/// sequences 为 (base_sequence, start_sequence)，分片序号相对 base_sequence 计算，
+/// 直播录制多次刷新播放列表时文件名保持连续；track 为空时追加的是视频分片
+async fn append_segments(
+    download_info_context: &DownloadInfoContext,
+    media_play_list: &MediaPlaylist,
+    download_source_info: &mut DownloadSourceInfo,
+    track: Option<&MediaTrack>,
+    sequences: (u64, u64),
+    current_map: &mut Option<(String, Option<(u64, u64)>)>,
+    index_file: &mut BufWriter<File>,
+) -> anyhow::Result<usize, Box<dyn std::error::Error>> {
+    let (base_sequence, start_sequence) = sequences;
+    let live = track.is_none() && download_source_info.live.is_some();
+    let (base_download_url, ts_path, container) = match track {
+        Some(track) => (track.media_url.clone(), &track.ts_path, track.container),
+        None => (
+            media_play_list_url(download_info_context, download_source_info),
+            &download_info_context.ts_path,
+            download_source_info.container,
+        ),
+    };
+    let base_download_url = &base_download_url;
+    // 索引文件中的文件数，包含 fMP4 的初始化分片
+    let mut count = 0;
+    // EXT-X-KEY 对其后的所有分片生效，直到出现下一个 EXT-X-KEY
+    let mut current_key: Option<(String, Option<String>)> = None;
+    // 资源地址 -> 上一个 BYTERANGE 分片的结束位置
+    let mut range_ends: HashMap<String, u64> = HashMap::new();
+    for (pos, segment) in media_play_list.segments.iter().enumerate() {
+        let sequence = media_play_list.media_sequence + pos as u64;
+        if let Some(k) = &segment.key {
+            current_key = match k.method {
+                KeyMethod::None => None,
+                _ => {
+                    if container == MediaContainer::Fmp4 && k.method == KeyMethod::SampleAES {
+                        return Err(Box::from("暂不支持 SAMPLE-AES 加密的 fMP4 分片"));
+                    }
+                    let key_url = key_url(base_download_url, k)?;
+                    if !download_source_info.keys.contains_key(key_url.as_str()) {
+                        let m3u8_encrypt_key = M3u8EncryptKey::from_key(&key_url, k).await?;
+                        download_source_info
+                            .keys
+                            .insert(key_url.to_string(), m3u8_encrypt_key);
+                    }
+                    Some((key_url.to_string(), k.iv.clone()))
                 }
-            }
+            };
         }
-        let base_download_url = &download_info_context.url;
-        if let Some(k) = &segment.key {
-            download_source_info.m3u8_encrypt_key =
-                M3u8EncryptKey::from_key(base_download_url, k).await?;
-        }
-        let s = format!("{} {} {}", "file", file_name_str, "\n");
-        index_file.write(s.as_bytes()).await?;
-        let url = base_download_url.join(&segment.uri).unwrap();
-        download_list.push(DownloadInfoDetail {
-            id: i,
-            url,
-            file_name: file_name.into_os_string().into_string().unwrap(),
-            data: None,
-            success: false,
+        let url = base_download_url.join(&segment.uri)?;
+        // 没有偏移的 BYTERANGE 紧接着同一资源上一个分片的结尾
+        let byte_range = segment.byte_range.as_ref().map(|r| {
+            let offset = r
+                .offset
+                .or_else(|| range_ends.get(url.as_str()).copied())
+                .unwrap_or(0);
+            range_ends.insert(url.to_string(), offset + r.length);
+            (offset, r.length)
         });
+        // 已经追加过的分片和广告分片只用于跟踪密钥和区间
+        if sequence < start_sequence
+            || (track.is_none() && download_source_info.ad_segments.contains(&pos))
+        {
+            continue;
+        }
+        let i = sequence.saturating_sub(base_sequence) as usize;
+        // 没有 IV 属性时使用分片的媒体序列号
+        let iv = current_key
+            .as_ref()
+            .map(|(_, iv)| iv.clone().unwrap_or_else(|| sequence_iv(sequence)));
+        let mut files = Vec::with_capacity(2);
+        if let Some(map) = &segment.map {
+            // EXT-X-MAP 的 BYTERANGE 没有偏移时从资源开头读取
+            let byte_range = map
+                .byte_range
+                .as_ref()
+                .map(|r| (r.offset.unwrap_or(0), r.length));
+            let map_key = (map.uri.clone(), byte_range);
+            if current_map.as_ref() != Some(&map_key) {
+                *current_map = Some(map_key);
+                let file_name = ts_path.join(format!("{}{:06}.mp4", INIT_SEGMENT_PREFIX, i));
+                files.push((base_download_url.join(&map.uri)?, file_name, byte_range));
+            }
+        }
+        let uri_file_name = Path::new(url.path())
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let file_name = match container {
+            // 同一资源的不同区间或直播中复用的文件名加上序号避免重名
+            MediaContainer::Ts if byte_range.is_some() || live => {
+                ts_path.join(format!("{:06}_{}", i, uri_file_name))
+            }
+            MediaContainer::Ts => ts_path.join(Path::new(&segment.uri).file_name().unwrap()),
+            // fMP4 分片地址常带查询参数或同名，按序号命名
+            MediaContainer::Fmp4 => ts_path.join(format!("{:06}.m4s", i)),
+        };
+        files.push((url, file_name, byte_range));
+
+        for (url, file_name, byte_range) in files {
+            count += 1;
+            let file_name_str = utils::get_path_name(&file_name);
+            let s = format!("{} {} {}", "file", file_name_str, "\n");
+            index_file.write_all(s.as_bytes()).await?;
+            download_source_info
+                .download_info_list
+                .push(DownloadInfoDetail {
+                    id: i,
+                    url,
+                    file_name: file_name.into_os_string().into_string().unwrap(),
+                    success: false,
+                    key: current_key.as_ref().map(|(key, _)| key.clone()),
+                    iv: iv.clone(),
+                    byte_range,
+                    track: track.map(|t| t.id),
+                    attempts: 0,
+                    failed: false,
+                    error: None,
+                });
+        }
     }
-    download_source_info.download_info_list = download_list;
-    let v = serde_json::to_string_pretty(&download_source_info)?;
+    Ok(count)
+}
+
+// 直播录制时分片地址相对于媒体播放列表地址解析
+fn media_play_list_url(
+    download_info_context: &DownloadInfoContext,
+    download_source_info: &DownloadSourceInfo,
+) -> Url {
+    match &download_source_info.live {
+        Some(live) => live.media_url.clone(),
+        None => download_info_context.url.clone(),
+    }
+}
+
+async fn save_source_info(
+    download_info_context: &DownloadInfoContext,
+    download_source_info: &DownloadSourceInfo,
+) -> anyhow::Result<(), Box<dyn std::error::Error>> {
+    let v = serde_json::to_string_pretty(download_source_info)?;
     let mut json_file = OpenOptions::new()
         .write(true)
         .truncate(true)
@@ -193,38 +488,173 @@ async fn parse_source(
         .open(&download_info_context.json_path)
         .await?;
     json_file.write_all(v.as_bytes()).await?;
+    Ok(())
+}
+
+async fn record_live(
+    download_info_context: &mut DownloadInfoContext,
+    control: &mut watch::Receiver<DownloadControl>,
+) -> anyhow::Result<DownloadEvent, Box<dyn std::error::Error>> {
+    // 录制时长上限，单位分钟，0 表示不限制
+    let max_duration = get_string("liveRecordMaxDuration")
+        .parse::<i64>()
+        .unwrap_or(0);
+    loop {
+        download_segments(download_info_context, control).await?;
+        if control::interrupted(control).is_some() {
+            return Ok(interrupted_response(download_info_context));
+        }
+
+        let v = std::fs::read_to_string(&download_info_context.json_path)?;
+        let mut download_source_info = serde_json::from_str::<DownloadSourceInfo>(&v)?;
+        let mut live = download_source_info
+            .live
+            .take()
+            .ok_or("下载清单缺少直播录制信息")?;
+        if take_live_record_stop(&download_info_context.id) {
+            info!("停止录制直播, id:{}", download_info_context.id);
+            break;
+        }
+        if max_duration > 0 && Local::now().timestamp() - live.start_time >= max_duration * 60 {
+            info!("直播录制达到时长上限, id:{}", download_info_context.id);
+            break;
+        }
+
+        // 等待期间收到暂停、取消或停止录制的指令时立即处理
+        tokio::select! {
+            _ = time::sleep(Duration::from_secs(live.target_duration.max(1))) => {}
+            Ok(_) = control.changed() => {}
+        }
+        if control::interrupted(control).is_some() {
+            return Ok(interrupted_response(download_info_context));
+        }
+        if take_live_record_stop(&download_info_context.id) {
+            info!("停止录制直播, id:{}", download_info_context.id);
+            break;
+        }
+        let content = download_request(&live.media_url).await?;
+        let media_play_list = match m3u8_rs::parse_playlist_res(&content) {
+            Ok(Playlist::MediaPlaylist(media_list)) => media_list,
+            _ => return Err(Box::from("媒体播放列表未找到")),
+        };
+
+        let index_file = OpenOptions::new()
+            .append(true)
+            .open(&download_info_context.index_path)
+            .await?;
+        let mut index_file = BufWriter::new(index_file);
+        let sequences = (live.base_sequence, live.next_sequence);
+        let mut current_map = live.current_map.take();
+        download_source_info.live = Some(live);
+        let added = append_segments(
+            download_info_context,
+            &media_play_list,
+            &mut download_source_info,
+            None,
+            sequences,
+            &mut current_map,
+            &mut index_file,
+        )
+        .await?;
+        index_file.flush().await?;
+        if let Some(live) = download_source_info.live.as_mut() {
+            live.current_map = current_map;
+            live.target_duration = media_play_list.target_duration;
+            live.next_sequence = live
+                .next_sequence
+                .max(media_play_list.media_sequence + media_play_list.segments.len() as u64);
+        }
+        save_source_info(download_info_context, &download_source_info).await?;
+
+        let count = download_info_context.count.unwrap_or(0) + added as i32;
+        download_info_context.count = Some(count);
+        let download_info_update = DownloadInfoUpdate {
+            id: download_info_context.id.clone(),
+            count: Some(count),
+            ..Default::default()
+        };
+        let _ = update_download_by_id(download_info_update);
+        publish(DownloadEvent::LiveRefresh(DownloadInfoResponse {
+            id: download_info_context.id.clone(),
+            status: "recordLive".to_string(),
+            count: Some(count as usize),
+            ..Default::default()
+        }));
+
+        if media_play_list.end_list {
+            info!("直播已结束, id:{}", download_info_context.id);
+            download_segments(download_info_context, control).await?;
+            if control::interrupted(control).is_some() {
+                return Ok(interrupted_response(download_info_context));
+            }
+            break;
+        }
+    }
 
     let download_info_update = DownloadInfoUpdate {
         id: download_info_context.id.clone(),
-        status: Some("downloadSlice".to_string()),
-        count: Some(count as i32),
-        download_status: Some("downloading".to_string()),
+        status: Some("checkSource".to_string()),
+        download_count: Some(download_info_context.download_count),
         ..Default::default()
     };
     let _ = update_download_by_id(download_info_update);
-    Ok(DownloadInfoResponse {
+    Ok(DownloadEvent::RecordLiveEnd(DownloadInfoResponse {
         id: download_info_context.id.clone(),
-        status: "downloadSlice".to_string(),
-        download_count: None,
-        count: Some(count),
-        download_status: Some("downloading".to_string()),
-        mes_type: "parseSourceEnd".to_string(),
-    })
+        status: "checkSource".to_string(),
+        download_count: Some(download_info_context.download_count),
+        count: download_info_context.count.map(|c| c as usize),
+        ..Default::default()
+    }))
 }
 
 async fn parse_m3u8(
     download_info_context: &mut DownloadInfoContext,
-) -> anyhow::Result<MediaPlaylist, Box<dyn std::error::Error>> {
+) -> anyhow::Result<(MediaPlaylist, Vec<MediaTrack>), Box<dyn std::error::Error>> {
     let content = download_request(&download_info_context.url).await?;
 
     match m3u8_rs::parse_playlist_res(&content) {
         Ok(Playlist::MasterPlaylist(master)) => {
-            let stream = master
-                .variants
-                .get(0)
+            let stream = download_info_context
+                .variant
+                .select(&master.variants)
                 .ok_or("请选定一个有效的媒体播放编号")?;
 
-            download_info_context.url = download_info_context.url.join(&stream.uri)?;
+            let master_url = download_info_context.url.clone();
+            download_info_context.url = master_url.join(&stream.uri)?;
+            download_info_context.bandwidth = Some(stream.bandwidth);
+
+            let mut renditions = download_info_context.audio_renditions.select(
+                &master.alternatives,
+                AlternativeMediaType::Audio,
+                stream.audio.as_deref(),
+            );
+            renditions.extend(download_info_context.subtitle_renditions.select(
+                &master.alternatives,
+                AlternativeMediaType::Subtitles,
+                stream.subtitles.as_deref(),
+            ));
+            let mut movie_path = download_info_context.index_path.clone();
+            movie_path.pop();
+            let mut tracks = Vec::with_capacity(renditions.len());
+            for (id, media) in renditions.into_iter().enumerate() {
+                tracks.push(MediaTrack {
+                    id,
+                    ty: if media.media_type == AlternativeMediaType::Audio {
+                        MediaTrackType::Audio
+                    } else {
+                        MediaTrackType::Subtitles
+                    },
+                    name: media.name.clone(),
+                    language: media.language.clone(),
+                    media_url: master_url.join(media.uri.as_deref().unwrap_or_default())?,
+                    container: MediaContainer::Ts,
+                    index_path: movie_path.join(format!(
+                        "{}_track_{}.txt",
+                        download_info_context.sub_title_name, id
+                    )),
+                    ts_path: download_info_context.ts_path.join(format!("track_{}", id)),
+                });
+            }
 
             let content1 = &download_request(&download_info_context.url).await?;
 
@@ -232,35 +662,143 @@ async fn parse_m3u8(
                 Ok(Playlist::MasterPlaylist(_)) => {
                     return Err(Box::from("媒体资源错误"));
                 }
-                Ok(Playlist::MediaPlaylist(media_list)) => return Ok(media_list),
+                Ok(Playlist::MediaPlaylist(media_list)) => return Ok((media_list, tracks)),
                 Err(_) => {
                     return Err(Box::from("媒体播放列表未找到"));
                 }
             }
         }
-        Ok(Playlist::MediaPlaylist(media_list)) => return Ok(media_list),
+        Ok(Playlist::MediaPlaylist(media_list)) => return Ok((media_list, Vec::new())),
         Err(_) => {
             return Err(Box::from("媒体播放列表未找到"));
         }
     }
 }
 
+async fn parse_media_play_list(url: &Url) -> anyhow::Result<MediaPlaylist> {
+    let content = download_request(url).await?;
+    match m3u8_rs::parse_playlist_res(&content) {
+        Ok(Playlist::MediaPlaylist(media_list)) => Ok(media_list),
+        _ => Err(anyhow::anyhow!("媒体播放列表未找到")),
+    }
+}
+
+pub async fn get_m3u8_variants(url: &Url) -> anyhow::Result<M3u8StreamList> {
+    let content = download_request(url).await?;
+
+    match m3u8_rs::parse_playlist_res(&content) {
+        Ok(Playlist::MasterPlaylist(master)) => Ok(M3u8StreamList {
+            variants: master
+                .variants
+                .iter()
+                .filter(|v| !v.is_i_frame)
+                .map(|v| M3u8Variant {
+                    url: url
+                        .join(&v.uri)
+                        .map(|u| u.to_string())
+                        .unwrap_or(v.uri.clone()),
+                    bandwidth: v.bandwidth,
+                    average_bandwidth: v.average_bandwidth,
+                    resolution: v.resolution.map(|r| format!("{}x{}", r.width, r.height)),
+                    codecs: v.codecs.clone(),
+                    frame_rate: v.frame_rate,
+                    variant: v
+                        .resolution
+                        .map(|r| format!("resolution:{}x{}", r.width, r.height)),
+                    audio: v.audio.clone(),
+                    subtitles: v.subtitles.clone(),
+                })
+                .collect(),
+            renditions: master
+                .alternatives
+                .iter()
+                .filter_map(|m| {
+                    let media_type = match m.media_type {
+                        AlternativeMediaType::Audio => MediaTrackType::Audio,
+                        AlternativeMediaType::Subtitles => MediaTrackType::Subtitles,
+                        _ => return None,
+                    };
+                    // 没有 URI 的音轨已经包含在媒体流中，无需单独选择
+                    let uri = m.uri.as_ref()?;
+                    Some(M3u8Rendition {
+                        media_type,
+                        group_id: m.group_id.clone(),
+                        name: m.name.clone(),
+                        language: m.language.clone(),
+                        default: m.default,
+                        url: url.join(uri).map(|u| u.to_string()).unwrap_or(uri.clone()),
+                    })
+                })
+                .collect(),
+        }),
+        // 媒体播放列表只有一个媒体流，无需选择
+        Ok(Playlist::MediaPlaylist(_)) => Ok(M3u8StreamList::default()),
+        Err(_) => Err(anyhow::anyhow!("媒体播放列表未找到")),
+    }
+}
+
 async fn download_slice(
     download_info_context: &mut DownloadInfoContext,
-    socket: &mut WebSocket<TcpStream>,
-) -> anyhow::Result<DownloadInfoResponse, Box<dyn std::error::Error>> {
+    control: &mut watch::Receiver<DownloadControl>,
+) -> anyhow::Result<DownloadEvent, Box<dyn std::error::Error>> {
+    if direct_download::is_direct(download_info_context) {
+        return direct_download::download_slice(download_info_context, control).await;
+    }
+    download_segments(download_info_context, control).await?;
+    if control::interrupted(control).is_some() {
+        return Ok(interrupted_response(download_info_context));
+    }
+
+    let download_info_update = DownloadInfoUpdate {
+        id: download_info_context.id.clone(),
+        status: Some("checkSource".to_string()),
+        download_count: Some(download_info_context.download_count),
+        ..Default::default()
+    };
+    let _ = update_download_by_id(download_info_update);
+    Ok(DownloadEvent::DownloadSliceEnd(DownloadInfoResponse {
+        id: download_info_context.id.clone(),
+        status: "checkSource".to_string(),
+        download_count: Some(download_info_context.download_count),
+        ..Default::default()
+    }))
+}
+
+/// 被暂停或取消时停留在当前阶段，恢复后从该阶段继续
+pub fn interrupted_response(download_info_context: &DownloadInfoContext) -> DownloadEvent {
+    DownloadEvent::StatusChange(DownloadInfoResponse {
+        id: download_info_context.id.clone(),
+        status: download_info_context.status.clone(),
+        download_count: Some(download_info_context.download_count),
+        ..Default::default()
+    })
+}
+
+/// 下载清单中所有未完成的分片，失败的分片保留在清单中等待重试
+async fn download_segments(
+    download_info_context: &mut DownloadInfoContext,
+    control: &mut watch::Receiver<DownloadControl>,
+) -> anyhow::Result<(), Box<dyn std::error::Error>> {
     let download_count = Arc::new(AtomicI32::new(download_info_context.download_count));
     let v = std::fs::read_to_string(&download_info_context.json_path)?;
-    let mut download_source_info = serde_json::from_str::<DownloadSourceInfo>(&v)?;
-    let queue = &read_data_to_queue(
-        &download_source_info,
-        &download_info_context.json_success_path,
-    );
+    let download_source_info = serde_json::from_str::<DownloadSourceInfo>(&v)?;
+    let manifest = load_manifest(download_info_context, &download_source_info)?;
+    let queue = &read_data_to_queue(&download_source_info, &manifest);
 
-    let (tx, mut rx): (
-        mpsc::Sender<DownloadInfoDetail>,
-        mpsc::Receiver<DownloadInfoDetail>,
-    ) = mpsc::channel(100);
+    // 本次下载的字节数和完成的分片数，用于计算速度和估算总大小
+    let mut meter = ProgressMeter::new(download_info_context.downloaded_bytes);
+    let mut session_bytes: u64 = 0;
+    let mut session_segments: u64 = 0;
+    let pending_segments = queue.len() as u64;
+    // 点播资源在下载初期按码率 × 时长估算，直播无法估算
+    let nominal_bytes = download_source_info
+        .bandwidth
+        .filter(|_| download_source_info.live.is_none() && download_source_info.duration > 0.0)
+        .map(|bandwidth| (bandwidth as f64 * download_source_info.duration / 8.0) as u64);
+
+    // 分片内容由分片任务直接写入文件，通道中只传递下载结果
+    let (tx, mut rx): (mpsc::Sender<SegmentResult>, mpsc::Receiver<SegmentResult>) =
+        mpsc::channel(100);
 
     // 创建进度发送 channel
     let (progress_tx, mut progress_rx): (mpsc::Sender<i32>, mpsc::Receiver<i32>) =
@@ -282,34 +820,86 @@ async fn download_slice(
         }
     });
 
+    // 单个任务的并发限制，先占用任务名额再占用全局名额，避免等待中的分片占住全局名额
+    let task_limiter = ConcurrencyLimiter::new(task_concurrency());
+    refresh_limits(&task_limiter);
+    // 单个任务的限速，所有分片共享同一个令牌桶
+    let task_bandwidth = TokenBucket::new(task_bandwidth());
+    refresh_bandwidth(Some(&task_bandwidth));
+    let max_attempts = segment_max_attempts();
+    let mut workers = Vec::new();
     loop {
         let queue_data = queue.pop();
         if queue_data.is_none() {
             break;
         }
         let detail = queue_data.unwrap();
-        let semaphore = Arc::new(Semaphore::new(6));
+        let task_limiter = task_limiter.clone();
+        let task_bandwidth = task_bandwidth.clone();
         let tx1 = tx.clone();
-        tokio::spawn(async move {
-            #[allow(unused_variables)]
-            let p = &semaphore.acquire().await;
-            let mut data = Vec::new();
-            let mut success = false;
-            if let Ok((s, d)) = download_ts(detail.url.as_str(), &detail.m3u8_encrypt_key).await {
-                success = s;
-                data = d;
-            }
-
-            tx1.send(DownloadInfoDetail {
+        workers.push(tokio::spawn(async move {
+            let mut result = DownloadInfoDetail {
                 id: detail.id.to_owned(),
                 url: detail.url.clone(),
                 file_name: detail.file_name.to_owned(),
-                data: Some(data),
-                success,
-            })
-            .await
-            .unwrap();
-        });
+                success: false,
+                key: detail.key.clone(),
+                iv: detail.iv.clone(),
+                byte_range: detail.byte_range,
+                track: detail.track,
+                attempts: 0,
+                failed: false,
+                error: None,
+            };
+            let mut size = 0;
+            let mut checksum = None;
+            // 单个分片失败后按指数退避重试，等待期间不占用并发名额
+            loop {
+                result.attempts += 1;
+                let downloaded = {
+                    let _task_permit = task_limiter.acquire().await;
+                    let _global_permit = GLOBAL_LIMITER.acquire().await;
+                    download_segment(
+                        detail.url.as_str(),
+                        detail.m3u8_encrypt_key.as_deref(),
+                        detail.iv.as_deref(),
+                        detail.byte_range,
+                        detail.container,
+                        Some(&task_bandwidth),
+                        &detail.file_name,
+                    )
+                    .await
+                };
+                match downloaded {
+                    Ok((written, crc)) => {
+                        result.success = true;
+                        result.error = None;
+                        size = written;
+                        checksum = Some(crc);
+                        break;
+                    }
+                    Err(e) => result.error = Some(e.to_string()),
+                }
+                if result.attempts >= max_attempts {
+                    info!(
+                        "分片下载失败：{}, 原因：{}",
+                        result.url,
+                        result.error.as_deref().unwrap_or_default()
+                    );
+                    result.failed = true;
+                    break;
+                }
+                time::sleep(retry_backoff(result.attempts)).await;
+            }
+
+            let _ = tx1
+                .send(SegmentResult {
+                    detail: result,
+                    size,
+                    checksum,
+                })
+                .await;
+        }));
         if queue.is_empty() {
             break;
         }
@@ -317,36 +907,37 @@ async fn download_slice(
 
     drop(tx);
 
-    (&mut download_source_info.download_info_list).clear();
-    let mut json_success_file = OpenOptions::new()
-        .append(true)
-        .open(&download_info_context.json_success_path)
-        .await?;
-
-    // 创建集合对象存储成功下载的文件名
-    let mut success_files: Vec<String> = Vec::new();
+    // 已经失败的分片不再自动重试，保留在清单中等待强制合并或手动重试
+    let mut failed_count = manifest
+        .values()
+        .filter(|segment| segment.status == SEGMENT_FAILED)
+        .count();
+    // 尚未写入分片清单的下载结果，每次发送进度时批量写入
+    let mut records: Vec<DownloadSegment> = Vec::new();
 
+    let mut interrupted = false;
     // 使用 tokio::select! 来同时处理文件下载和进度发送
     loop {
         tokio::select! {
+            // 用户暂停、取消下载或停止录制时停止所有分片任务
+            Ok(_) = control.changed() => {
+                if control::stopping(control) {
+                    interrupted = true;
+                    break;
+                }
+            }
             // 处理文件下载结果
             res = rx.recv() => {
                 match res {
-                    Some(mut res) => {
-                        if res.success {
-                            let file_name = res.file_name.clone();
-                            let rs = downloaded_file_save(res.clone()).await;
-                            if rs.is_ok() {
-                                let _ = download_count.fetch_add(1, Ordering::Relaxed);
-                                // 将成功下载的文件名放入集合对象中
-                                success_files.push(file_name);
-                            } else {
-                                res.data = None;
-                                download_source_info.download_info_list.push(res);
-                            }
-                        } else {
-                            download_source_info.download_info_list.push(res);
+                    Some(SegmentResult { detail, size, checksum }) => {
+                        if detail.success {
+                            let _ = download_count.fetch_add(1, Ordering::Relaxed);
+                            session_bytes += size;
+                            session_segments += 1;
+                        } else if detail.failed {
+                            failed_count += 1;
                         }
+                        records.push(segment_record(&download_info_context.id, &detail, size, checksum));
                     }
                     _none => {
                         // 所有文件下载完成，立即停止进度发送任务
@@ -363,27 +954,47 @@ async fn download_slice(
             progress_count = progress_rx.recv() => {
                 match progress_count {
                     Some(count) => {
-                        // 在这里记录成功下载的文件到文件中
-                        if !success_files.is_empty() {
-                            let batch_content = success_files
-                                .iter()
-                                .map(|name| format!("{}", name))
-                                .collect::<String>();
-                            let _ = json_success_file.write(batch_content.as_bytes()).await?;
-                            success_files.clear(); // 清空已处理的文件名
+                        // 写入失败时保留记录，下次发送进度时重试
+                        match save_segments(&records) {
+                            Ok(_) => records.clear(),
+                            Err(e) => error!("写入分片清单失败: {}", e),
                         }
 
+                        let total_bytes = estimate_total_bytes(
+                            download_info_context.downloaded_bytes + session_bytes,
+                            (session_bytes, session_segments),
+                            pending_segments.saturating_sub(session_segments),
+                            nominal_bytes,
+                        );
+                        let sample = meter.sample(session_bytes, total_bytes);
                         let download_info_update = DownloadInfoUpdate {
                             id: download_info_context.id.clone(),
                             download_count: Some(count),
+                            downloaded_bytes: Some(sample.downloaded_bytes as i64),
+                            total_bytes: sample.total_bytes.map(|b| b as i64),
+                            speed: Some(sample.speed as i64),
+                            average_speed: Some(sample.average_speed as i64),
+                            eta: sample.eta.map(|eta| eta as i64),
                             ..Default::default()
                         };
                         let _ = update_download_by_id(download_info_update);
-                        let _ = socket.send(tungstenite::Message::text(serde_json::to_string(&json!({
-                            "id": download_info_context.id,
-                            "download_count": count,
-                            "mes_type": "progress",
-                        }))?));
+                        refresh_limits(&task_limiter);
+                        refresh_bandwidth(Some(&task_bandwidth));
+                        publish(DownloadEvent::Progress(DownloadProgress {
+                            id: download_info_context.id.clone(),
+                            download_count: count,
+                            failed_count,
+                            concurrency: task_limiter.limit(),
+                            active: task_limiter.active(),
+                            global_concurrency: GLOBAL_LIMITER.limit(),
+                            bandwidth_limit: task_bandwidth.rate(),
+                            global_bandwidth_limit: GLOBAL_BANDWIDTH.rate(),
+                            downloaded_bytes: sample.downloaded_bytes,
+                            total_bytes: sample.total_bytes,
+                            speed: sample.speed,
+                            average_speed: sample.average_speed,
+                            eta: sample.eta,
+                        }));
                     }
                     _none => {
                          // 进度发送通道已关闭，退出循环
@@ -402,95 +1013,238 @@ async fn download_slice(
         drop(tx);
     }
 
-    // 确保所有剩余的成功文件都被写入
-    if !success_files.is_empty() {
-        let batch_content = success_files
-            .iter()
-            .map(|name| format!("{}\n", name))
-            .collect::<String>();
-        let _ = json_success_file.write(batch_content.as_bytes()).await?;
-        info!("写入剩余的{}个成功文件记录", success_files.len());
+    if interrupted {
+        for worker in &workers {
+            worker.abort();
+        }
+        for worker in workers {
+            let _ = worker.await;
+        }
+        // 中断前已经下载完成的分片照常保存
+        while let Ok(SegmentResult {
+            detail,
+            size,
+            checksum,
+        }) = rx.try_recv()
+        {
+            if detail.success {
+                let _ = download_count.fetch_add(1, Ordering::Relaxed);
+                session_bytes += size;
+                records.push(segment_record(
+                    &download_info_context.id,
+                    &detail,
+                    size,
+                    checksum,
+                ));
+            }
+        }
     }
 
-    let v = serde_json::to_string_pretty(&download_source_info)?;
-    let mut json_file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(&download_info_context.json_path)
-        .await?;
-    json_file.write_all(v.as_bytes()).await?;
-    download_info_context.download_count = download_count.load(Ordering::Relaxed);
+    // 确保所有剩余的下载结果都写入分片清单
+    if !records.is_empty() {
+        save_segments(&records)?;
+        info!("写入剩余的{}个分片记录", records.len());
+    }
 
+    // 下载停止后速度归零，已下载的字节数在恢复下载时继续累加
+    download_info_context.downloaded_bytes += session_bytes;
     let download_info_update = DownloadInfoUpdate {
         id: download_info_context.id.clone(),
-        status: Some("checkSource".to_string()),
-        download_count: Some(download_info_context.download_count),
+        downloaded_bytes: Some(download_info_context.downloaded_bytes as i64),
+        speed: Some(0),
         ..Default::default()
     };
     let _ = update_download_by_id(download_info_update);
-    Ok(DownloadInfoResponse {
-        id: download_info_context.id.clone(),
-        status: "checkSource".to_string(),
-        download_count: Some(download_info_context.download_count),
-        mes_type: "downloadSliceEnd".to_string(),
-        ..Default::default()
-    })
+
+    download_info_context.download_count = download_count.load(Ordering::Relaxed);
+    Ok(())
 }
 
-pub fn read_data_to_queue(
+/// 清除失败记录，使失败的分片在下次下载时重新尝试，返回重置的分片数
+pub async fn reset_failed_segments(
+    download_info_context: &DownloadInfoContext,
+) -> anyhow::Result<usize, Box<dyn std::error::Error>> {
+    let v = std::fs::read_to_string(&download_info_context.json_path)?;
+    let download_source_info = serde_json::from_str::<DownloadSourceInfo>(&v)?;
+    // 先导入旧版本的记录，旧清单中的失败标记也一起清除
+    load_manifest(download_info_context, &download_source_info)?;
+    Ok(download_segment::service::reset_failed_segments(
+        &download_info_context.id,
+    )?)
+}
+
+/// 读取分片清单，返回分片文件路径 -> 分片记录
+///
+/// 成功记录的分片文件不存在或大小不一致时视为未下载，崩溃或文件被删除后重新下载这些分片
+fn load_manifest(
+    download_info_context: &DownloadInfoContext,
     download_source_info: &DownloadSourceInfo,
-    json_success_path: &PathBuf,
-) -> SegQueue<DownloadInfoQueueDetail> {
-    let queue: SegQueue<DownloadInfoQueueDetail> = SegQueue::new();
-    let download_info_list = &download_source_info.download_info_list;
-    if utils::exists(&PathBuf::from(json_success_path)) {
-        let success_v = &std::fs::read_to_string(json_success_path).unwrap();
-        for download_info in download_info_list {
-            if !success_v.contains(&download_info.file_name) {
-                queue.push(DownloadInfoQueueDetail {
-                    id: download_info.id,
-                    url: download_info.url.clone(),
-                    file_name: download_info.file_name.clone(),
-                    m3u8_encrypt_key: Arc::new(download_source_info.m3u8_encrypt_key.clone()),
-                });
+) -> anyhow::Result<HashMap<String, DownloadSegment>> {
+    import_legacy_manifest(download_info_context, download_source_info)?;
+    let mut manifest = HashMap::new();
+    for segment in get_segments(&download_info_context.id)? {
+        if segment.status == SEGMENT_SUCCESS {
+            let size = std::fs::metadata(&segment.file_name).map(|m| m.len() as i64);
+            if size.ok() != Some(segment.size) {
+                continue;
             }
         }
-    } else {
-        let _ = utils::create_file(&PathBuf::from(json_success_path));
-        for download_info in download_info_list {
-            queue.push(DownloadInfoQueueDetail {
-                id: download_info.id,
-                url: download_info.url.clone(),
-                file_name: download_info.file_name.clone(),
-                m3u8_encrypt_key: Arc::new(download_source_info.m3u8_encrypt_key.clone()),
-            });
-        }
+        manifest.insert(segment.file_name.clone(), segment);
     }
-    queue
+    Ok(manifest)
 }
 
-pub async fn downloaded_file_save(p: DownloadInfoDetail) -> anyhow::Result<(), tokio::io::Error> {
-    let mut file = File::create(&p.file_name).await?;
-    if let Some(data) = p.data {
-        file.write_all(&data).await?;
+// 旧版本在 _success.json 中记录已完成的文件名，进度刷新时写入的记录之间没有换行，
+// 只导入文件仍然存在的分片，导入后删除旧文件
+fn import_legacy_manifest(
+    download_info_context: &DownloadInfoContext,
+    download_source_info: &DownloadSourceInfo,
+) -> anyhow::Result<()> {
+    let legacy_path = &download_info_context.json_success_path;
+    if !utils::exists(legacy_path) {
+        return Ok(());
     }
+    let legacy = std::fs::read_to_string(legacy_path).unwrap_or_default();
+    let ts_prefix = download_info_context.ts_path.join("");
+    let legacy = legacy_success_files(&legacy, &ts_prefix.to_string_lossy());
+    let mut segments = Vec::new();
+    for detail in &download_source_info.download_info_list {
+        if detail.failed {
+            segments.push(segment_record(&download_info_context.id, detail, 0, None));
+        } else if legacy.contains(detail.file_name.as_str()) {
+            if let Ok(metadata) = std::fs::metadata(&detail.file_name) {
+                let mut detail = detail.clone();
+                detail.success = true;
+                segments.push(segment_record(
+                    &download_info_context.id,
+                    &detail,
+                    metadata.len(),
+                    None,
+                ));
+            }
+        }
+    }
+    save_segments(&segments)?;
+    std::fs::remove_file(legacy_path)?;
     Ok(())
 }
 
+// 旧版成功记录中的文件名，部分版本写入时没有换行，按分片目录前缀拆分后精确匹配
+fn legacy_success_files<'a>(legacy: &'a str, ts_prefix: &str) -> HashSet<&'a str> {
+    let mut files = HashSet::new();
+    for line in legacy.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let mut starts: Vec<usize> = line.match_indices(ts_prefix).map(|(i, _)| i).collect();
+        if starts.first() != Some(&0) {
+            starts.insert(0, 0);
+        }
+        starts.push(line.len());
+        files.extend(starts.windows(2).map(|w| &line[w[0]..w[1]]));
+    }
+    files
+}
+
+fn segment_record(
+    download_id: &str,
+    detail: &DownloadInfoDetail,
+    size: u64,
+    checksum: Option<u32>,
+) -> DownloadSegment {
+    DownloadSegment {
+        download_id: download_id.to_string(),
+        file_name: detail.file_name.clone(),
+        status: if detail.success {
+            SEGMENT_SUCCESS
+        } else {
+            SEGMENT_FAILED
+        }
+        .to_string(),
+        size: size as i64,
+        checksum: checksum.map(|crc| format!("{:08x}", crc)),
+        attempts: detail.attempts as i32,
+        error: detail.error.clone(),
+        update_time: Some(utils::get_current_time_str()),
+    }
+}
+
+/// 分片清单中没有记录的分片加入下载队列，已完成和多次重试仍失败的分片跳过
+pub fn read_data_to_queue(
+    download_source_info: &DownloadSourceInfo,
+    manifest: &HashMap<String, DownloadSegment>,
+) -> SegQueue<DownloadInfoQueueDetail> {
+    let queue: SegQueue<DownloadInfoQueueDetail> = SegQueue::new();
+    let keys: HashMap<&String, Arc<M3u8EncryptKey>> = download_source_info
+        .keys
+        .iter()
+        .map(|(key_url, key)| (key_url, Arc::new(key.clone())))
+        .collect();
+    let to_queue_detail = |download_info: &DownloadInfoDetail| DownloadInfoQueueDetail {
+        id: download_info.id,
+        url: download_info.url.clone(),
+        file_name: download_info.file_name.clone(),
+        key: download_info.key.clone(),
+        iv: download_info.iv.clone(),
+        byte_range: download_info.byte_range,
+        track: download_info.track,
+        container: match download_info
+            .track
+            .and_then(|id| download_source_info.tracks.iter().find(|t| t.id == id))
+        {
+            Some(track) if track.ty == MediaTrackType::Subtitles => None,
+            Some(track) => Some(track.container),
+            None => Some(download_source_info.container),
+        },
+        m3u8_encrypt_key: download_info
+            .key
+            .as_ref()
+            .and_then(|key_url| keys.get(key_url).cloned()),
+    };
+    for download_info in download_source_info
+        .download_info_list
+        .iter()
+        .filter(|download_info| !manifest.contains_key(&download_info.file_name))
+    {
+        queue.push(to_queue_detail(download_info));
+    }
+    queue
+}
+
 async fn check_source(
     download_info_context: &mut DownloadInfoContext,
-) -> anyhow::Result<DownloadInfoResponse, Box<dyn std::error::Error>> {
-    let mut downloaded = true;
+) -> anyhow::Result<DownloadEvent, Box<dyn std::error::Error>> {
+    if direct_download::is_direct(download_info_context) {
+        return direct_download::check_source(download_info_context).await;
+    }
     let v = std::fs::read_to_string(download_info_context.json_path.clone())?;
     let download_source_info = serde_json::from_str::<DownloadSourceInfo>(&v)?;
-    if download_source_info.download_info_list.len() != 0 {
-        downloaded = false;
-        thread::sleep(Duration::from_secs(10));
-    }
-    let mut status = "downloadSlice".to_string();
-    if downloaded {
-        status = "merger".to_string();
+    let manifest = load_manifest(download_info_context, &download_source_info)?;
+    let mut failed_count = 0;
+    let mut pending_count = 0;
+    for detail in &download_source_info.download_info_list {
+        match manifest.get(&detail.file_name) {
+            Some(segment) if segment.status == SEGMENT_FAILED => failed_count += 1,
+            Some(_) => {}
+            None => pending_count += 1,
+        }
     }
+    let status = if download_source_info.live.is_some() {
+        // 直播分片过期后无法重新下载，失败和停止录制时未完成的分片作为空缺直接合并
+        if failed_count + pending_count > 0 {
+            info!(
+                "直播录制缺少 {} 个分片, id:{}",
+                failed_count + pending_count,
+                download_info_context.id
+            );
+        }
+        "merger".to_string()
+    } else if pending_count > 0 {
+        "downloadSlice".to_string()
+    } else if failed_count > 0 {
+        // 停在检查阶段，由用户选择强制合并或只重试失败的分片
+        download_info_context.status = "checkSource".to_string();
+        return Err(Box::new(PartialDownload { failed_count }));
+    } else {
+        "merger".to_string()
+    };
 
     let download_info_update = DownloadInfoUpdate {
         id: download_info_context.id.clone(),
@@ -498,22 +1252,35 @@ async fn check_source(
         ..Default::default()
     };
     let _ = update_download_by_id(download_info_update);
-    Ok(DownloadInfoResponse {
+    Ok(DownloadEvent::CheckSourceEnd(DownloadInfoResponse {
         id: download_info_context.id.clone(),
         status,
-        mes_type: "checkSourceEnd".to_string(),
         ..Default::default()
-    })
+    }))
 }
 
 pub async fn merger(
     download_info_context: &mut DownloadInfoContext,
-) -> anyhow::Result<DownloadInfoResponse, Box<dyn std::error::Error>> {
+) -> anyhow::Result<DownloadEvent, Box<dyn std::error::Error>> {
+    if direct_download::is_direct(download_info_context) {
+        return direct_download::merger(download_info_context).await;
+    }
     let index_str = utils::get_path_name(&download_info_context.index_path);
     clear_download_fail_ts(index_str.clone()).await?;
     let mv_str = index_str.replace("txt", "mp4");
-    File::create(Path::new(&mv_str)).await?;
     info!("开始合并视频, index:{}", index_str.clone());
+    let (container, tracks) = std::fs::read_to_string(&download_info_context.json_path)
+        .ok()
+        .and_then(|v| serde_json::from_str::<DownloadSourceInfo>(&v).ok())
+        .map(|d| (d.container, d.tracks))
+        .unwrap_or_default();
+    let audio_tracks: Vec<&MediaTrack> = tracks
+        .iter()
+        .filter(|t| t.ty == MediaTrackType::Audio)
+        .collect();
+    for track in &audio_tracks {
+        clear_download_fail_ts(utils::get_path_name(&track.index_path)).await?;
+    }
     let platform = tauri_plugin_os::platform();
     let mut exe_path = utils::app_install_root().join("resources").join(platform);
     if platform == "macos" {
@@ -521,52 +1288,311 @@ pub async fn merger(
     } else {
         exe_path = exe_path.join("ffmpeg");
     }
-    let mut command = Command::new(exe_path);
-    command.args([
-        "-y",
-        "-f",
-        "concat",
-        "-safe",
-        "0",
-        "-i",
-        &index_str,
-        "-bsf:a",
-        "aac_adtstoasc",
-        "-c",
-        "copy",
-        &mv_str,
-    ]);
-    #[cfg(target_os = "windows")]
-    {
-        use std::os::windows::process::CommandExt;
-        command.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    // mergeMode: ffmpeg 使用 ffmpeg 合并（默认） native 直接拼接分片
+    let mut native = get_string("mergeMode") == "native";
+    if !native && !exe_path.exists() {
+        info!("未找到 ffmpeg，直接拼接分片, path:{:?}", exe_path);
+        native = true;
     }
-    let output = command.output()?;
-    if output.status.success() {
-        tokio::spawn(delete_m3u8_tmp_file(
-            index_str,
-            download_info_context.sub_title_name.clone(),
-        ));
+    let output_str = if native {
+        native_merger(
+            &download_info_context.index_path,
+            container,
+            &audio_tracks,
+            &mv_str,
+        )
+        .await?
+    } else {
+        File::create(Path::new(&mv_str)).await?;
+        let concat_str = concat_list(
+            &download_info_context.index_path,
+            &download_info_context.ts_path,
+            container,
+        )
+        .await?;
+        let mut audio_lists = Vec::with_capacity(audio_tracks.len());
+        for track in &audio_tracks {
+            audio_lists
+                .push(concat_list(&track.index_path, &track.ts_path, track.container).await?);
+        }
+        let mut command = Command::new(exe_path);
+        command.args(["-y", "-f", "concat", "-safe", "0", "-i", &concat_str]);
+        for audio_list in &audio_lists {
+            command.args(["-f", "concat", "-safe", "0", "-i", audio_list]);
+        }
+        if !audio_tracks.is_empty() {
+            // 视频在前，选中的音轨依次排在后面，媒体流自带的音频放在最后
+            command.args(["-map", "0:v:0"]);
+            for (n, track) in audio_tracks.iter().enumerate() {
+                command.args(["-map", &format!("{}:a", n + 1)]);
+                let metadata = format!("-metadata:s:{}", n + 1);
+                if let Some(language) = &track.language {
+                    command.args([&metadata, &format!("language={}", language)]);
+                }
+                command.args([&metadata, &format!("title={}", track.name)]);
+            }
+            command.args(["-map", "0:a?"]);
+        }
+        // aac_adtstoasc 不支持 AC-3 等其他编码，只对 ADTS 封装的 AAC 音频流使用
+        for n in
+            adts_audio_outputs(&download_info_context.index_path, container, &audio_tracks).await
+        {
+            command.args([&format!("-bsf:a:{}", n), "aac_adtstoasc"]);
+        }
+        command.args(["-c", "copy", &mv_str]);
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::process::CommandExt;
+            command.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+        match command.output() {
+            Ok(output) if output.status.success() => mv_str.clone(),
+            Ok(output) => {
+                let s = String::from_utf8_lossy(&output.stderr);
+                error!("合并视频错误：{}", s);
+                return Err(Box::from(s));
+            }
+            // ffmpeg 被删除或没有执行权限时退回直接拼接
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::PermissionDenied) => {
+                error!("ffmpeg 无法执行，直接拼接分片：{}", e);
+                let _ = remove_file(&mv_str).await;
+                native_merger(
+                    &download_info_context.index_path,
+                    container,
+                    &audio_tracks,
+                    &mv_str,
+                )
+                .await?
+            }
+            Err(e) => return Err(Box::from(e)),
+        }
+    };
 
-        let download_info_update = DownloadInfoUpdate {
-            id: download_info_context.id.clone(),
-            status: Some("downloadEnd".to_string()),
-            download_status: Some("downloadSuccess".to_string()),
-            ..Default::default()
+    for track in tracks.iter().filter(|t| t.ty == MediaTrackType::Subtitles) {
+        if let Err(e) = merge_subtitle(track, &output_str).await {
+            error!("合并字幕错误：{}, {}", track.name, e);
+        }
+    }
+    tokio::spawn(delete_m3u8_tmp_file(
+        download_info_context.id.clone(),
+        index_str,
+        download_info_context.sub_title_name.clone(),
+        tracks.iter().map(|t| t.index_path.clone()).collect(),
+    ));
+
+    let download_info_update = DownloadInfoUpdate {
+        id: download_info_context.id.clone(),
+        status: Some("downloadEnd".to_string()),
+        download_status: Some("downloadSuccess".to_string()),
+        ..Default::default()
+    };
+    let _ = update_download_by_id(download_info_update);
+    Ok(DownloadEvent::End(DownloadInfoResponse {
+        id: download_info_context.id.clone(),
+        status: "downloadEnd".to_string(),
+        download_status: Some("downloadSuccess".to_string()),
+        ..Default::default()
+    }))
+}
+
+/// 不依赖 ffmpeg 合并：TS 分片首尾相接保存为 .ts，fMP4 分片接在初始化分片后保存为 .mp4，
+/// 选中的音轨保存为同名的外挂音频，返回视频文件路径
+async fn native_merger(
+    index_path: &Path,
+    container: MediaContainer,
+    audio_tracks: &[&MediaTrack],
+    mv_str: &str,
+) -> anyhow::Result<String> {
+    let output_path = match container {
+        MediaContainer::Ts => Path::new(mv_str).with_extension("ts"),
+        MediaContainer::Fmp4 => PathBuf::from(mv_str),
+    };
+    concat_segments(index_path, &output_path).await?;
+    for track in audio_tracks {
+        let suffix = track.language.as_deref().unwrap_or(&track.name);
+        let ext = match track.container {
+            MediaContainer::Ts => "ts",
+            MediaContainer::Fmp4 => "m4a",
         };
-        let _ = update_download_by_id(download_info_update);
-        Ok(DownloadInfoResponse {
-            id: download_info_context.id.clone(),
-            status: "downloadEnd".to_string(),
-            download_status: Some("downloadSuccess".to_string()),
-            mes_type: "end".to_string(),
-            ..Default::default()
-        })
-    } else {
-        let s = String::from_utf8_lossy(&output.stderr);
-        error!("合并视频错误：{}", s);
-        return Err(Box::from(s));
+        let track_path = Path::new(mv_str).with_extension(format!("{}.{}", suffix, ext));
+        concat_segments(&track.index_path, &track_path).await?;
+    }
+    Ok(utils::get_path_name(&output_path))
+}
+
+async fn concat_segments(index_path: &Path, output_path: &Path) -> anyhow::Result<()> {
+    let files = read_index_files(index_path).await?;
+    if files.iter().filter(|f| is_init_segment(f)).count() > 1 {
+        return Err(anyhow::anyhow!("存在多个初始化分片，需要使用 ffmpeg 合并"));
+    }
+    let mut output = File::create(output_path).await?;
+    for file in files {
+        let mut input = File::open(&file).await?;
+        tokio::io::copy(&mut input, &mut output).await?;
     }
+    output.flush().await?;
+    Ok(())
+}
+
+// 读取第一个分片开头的 PAT、PMT 判断音频流的编码
+const PMT_PROBE_SIZE: u64 = 188 * 64;
+
+async fn ts_audio_streams(index_path: &Path, container: MediaContainer) -> Option<Vec<bool>> {
+    if container != MediaContainer::Ts {
+        return None;
+    }
+    let first = read_index_files(index_path)
+        .await
+        .ok()?
+        .into_iter()
+        .next()?;
+    let mut data = Vec::new();
+    File::open(first)
+        .await
+        .ok()?
+        .take(PMT_PROBE_SIZE)
+        .read_to_end(&mut data)
+        .await
+        .ok()?;
+    sample_aes::ts_audio_streams(&data)
+}
+
+/// 输出文件中 ADTS 封装的 AAC 音频流序号，顺序与 merger 中的 -map 一致。
+/// fMP4 中的 AAC 已经是 ASC 格式，无法判断编码的流交给 mp4 封装器自动处理
+async fn adts_audio_outputs(
+    index_path: &Path,
+    container: MediaContainer,
+    audio_tracks: &[&MediaTrack],
+) -> Vec<usize> {
+    let mut outputs = Vec::new();
+    let mut next = 0;
+    for track in audio_tracks {
+        if track.container == MediaContainer::Fmp4 {
+            next += 1;
+            continue;
+        }
+        match ts_audio_streams(&track.index_path, track.container).await {
+            Some(streams) => {
+                outputs.extend(
+                    streams
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, adts)| **adts)
+                        .map(|(i, _)| next + i),
+                );
+                next += streams.len();
+            }
+            // 后面的流序号无法确定
+            None => return outputs,
+        }
+    }
+    if let Some(streams) = ts_audio_streams(index_path, container).await {
+        // 没有指定 -map 时 ffmpeg 只选择一路音频，只有一路时才能确定是哪一路
+        if audio_tracks.is_empty() && streams.len() > 1 {
+            return outputs;
+        }
+        outputs.extend(
+            streams
+                .iter()
+                .enumerate()
+                .filter(|(_, adts)| **adts)
+                .map(|(i, _)| next + i),
+        );
+    }
+    outputs
+}
+
+async fn read_index_files(index_path: &Path) -> anyhow::Result<Vec<PathBuf>, tokio::io::Error> {
+    let mut files = Vec::new();
+    let mut lines = BufReader::new(File::open(index_path).await?).lines();
+    while let Some(line) = lines.next_line().await? {
+        let file_path_str = line.trim().trim_start_matches("file ").trim();
+        if !file_path_str.is_empty() {
+            files.push(PathBuf::from(file_path_str));
+        }
+    }
+    Ok(files)
+}
+
+fn is_init_segment(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with(INIT_SEGMENT_PREFIX))
+}
+
+// 返回 ffmpeg concat 使用的文件列表
+async fn concat_list(
+    index_path: &Path,
+    ts_path: &Path,
+    container: MediaContainer,
+) -> anyhow::Result<String, tokio::io::Error> {
+    match container {
+        MediaContainer::Ts => Ok(utils::get_path_name(index_path)),
+        MediaContainer::Fmp4 => concat_fmp4_parts(index_path, ts_path).await,
+    }
+}
+
+/// 按初始化分片把 fMP4 分片拼接成若干个可独立播放的片段，返回片段的 ffmpeg 合并列表
+async fn concat_fmp4_parts(
+    index_path: &Path,
+    ts_path: &Path,
+) -> anyhow::Result<String, tokio::io::Error> {
+    let mut parts: Vec<PathBuf> = Vec::new();
+    let mut part_file: Option<File> = None;
+    for path in read_index_files(index_path).await? {
+        let is_init = is_init_segment(&path);
+        // 初始化分片变化时开始新的片段
+        if is_init || part_file.is_none() {
+            if let Some(mut f) = part_file.take() {
+                f.flush().await?;
+            }
+            let part_path = ts_path.join(format!("part_{:03}.mp4", parts.len()));
+            part_file = Some(File::create(&part_path).await?);
+            parts.push(part_path);
+        }
+        if let Some(f) = part_file.as_mut() {
+            f.write_all(&tokio::fs::read(&path).await?).await?;
+        }
+    }
+    if let Some(mut f) = part_file.take() {
+        f.flush().await?;
+    }
+
+    let parts_path = ts_path.join("parts.txt");
+    let content = parts
+        .iter()
+        .map(|p| format!("file {}\n", utils::get_path_name(p)))
+        .collect::<String>();
+    tokio::fs::write(&parts_path, content).await?;
+    Ok(utils::get_path_name(&parts_path))
+}
+
+/// 把 WebVTT 字幕分片拼接成与视频同名的外挂字幕，如 movie.zh.vtt
+async fn merge_subtitle(track: &MediaTrack, mv_str: &str) -> anyhow::Result<()> {
+    let content = tokio::fs::read_to_string(&track.index_path).await?;
+    let mut vtt = String::from("WEBVTT\n\n");
+    for line in content.lines() {
+        let file_path_str = line.trim().trim_start_matches("file ").trim();
+        if file_path_str.is_empty() {
+            continue;
+        }
+        let Ok(segment) = tokio::fs::read_to_string(file_path_str).await else {
+            continue;
+        };
+        let segment = segment.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+        if !segment.starts_with("WEBVTT") {
+            return Err(anyhow::anyhow!("不支持的字幕格式"));
+        }
+        // 每个分片都带有 WEBVTT 头，只保留第一个空行之后的字幕内容
+        if let Some((_, cues)) = segment.split_once("\n\n") {
+            vtt.push_str(cues.trim_matches('\n'));
+            vtt.push_str("\n\n");
+        }
+    }
+    let suffix = track.language.as_deref().unwrap_or(&track.name);
+    let vtt_path = Path::new(mv_str).with_extension(format!("{}.vtt", suffix));
+    tokio::fs::write(vtt_path, vtt).await?;
+    Ok(())
 }
 
 async fn clear_download_fail_ts(index_str: String) -> anyhow::Result<(), tokio::io::Error> {
@@ -622,8 +1648,10 @@ async fn clear_download_fail_ts(index_str: String) -> anyhow::Result<(), tokio::
 }
 
 async fn delete_m3u8_tmp_file(
+    download_id: String,
     index_str: String,
     sub_title_name: String,
+    track_index_paths: Vec<PathBuf>,
 ) -> anyhow::Result<(), tokio::io::Error> {
     // 休眠1分钟之后删除临时文件，避免ffmpeg进程未结束导致文件合并失败
     time::sleep(Duration::from_secs(20)).await;
@@ -631,11 +1659,32 @@ async fn delete_m3u8_tmp_file(
     index_path.pop();
     let ts_path = index_path.join("ts");
     let _ = remove_dir_all(ts_path).await;
+    for track_index_path in track_index_paths {
+        let _ = remove_file(track_index_path).await;
+    }
     let index_json = format!("{}.json", &sub_title_name);
     remove_file(index_path.join(&index_json)).await?;
     let txt = format!("{}.txt", &sub_title_name);
     remove_file(index_path.join(&txt)).await?;
-    let success_json = format!("{}_success.json", &sub_title_name);
-    remove_file(index_path.join(&success_json)).await?;
+    if let Err(e) = delete_segments(&download_id) {
+        error!("删除分片清单失败: {}", e);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_success_files_match_exact_names() {
+        let legacy = "/dl/a/ts/1.ts/dl/a/ts/2.ts\n/dl/a/ts/11.ts\n\n";
+        let files = legacy_success_files(legacy, "/dl/a/ts/");
+        assert_eq!(
+            files,
+            HashSet::from(["/dl/a/ts/1.ts", "/dl/a/ts/2.ts", "/dl/a/ts/11.ts"])
+        );
+        assert!(!files.contains("/dl/a/ts/12.ts"));
+        assert!(!legacy_success_files("/dl/a/ts/11.ts", "/dl/a/ts/").contains("/dl/a/ts/1.ts"));
+    }
+}
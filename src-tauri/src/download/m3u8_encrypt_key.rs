@@ -1,14 +1,20 @@
+use std::time::Duration;
+
 use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
 use anyhow::Error;
+use lazy_static::lazy_static;
 use m3u8_rs::{Key as m3u8Key, KeyMethod};
-use nom::{bytes::complete::tag, AsBytes, IResult};
+use moka::sync::Cache;
+use nom::{branch::alt, bytes::complete::tag, IResult, Parser};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use super::util::download_request;
+use super::{sample_aes::decrypt_ts, util::download_request};
 
 type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
 
+const AES_BLOCK_SIZE: usize = 16;
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum KeyType {
     None,
@@ -19,84 +25,282 @@ pub enum KeyType {
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct M3u8EncryptKey {
     pub ty: KeyType,
-    iv: Option<String>,
     content: Vec<u8>,
 }
 
+lazy_static! {
+    // 按密钥地址缓存已下载的密钥，同一个密钥只下载一次
+    static ref KEY_CACHE: Cache<String, Vec<u8>> = Cache::builder()
+        .max_capacity(1024)
+        .time_to_live(Duration::from_secs(60 * 60))
+        .build();
+}
+
 impl Default for M3u8EncryptKey {
     fn default() -> Self {
         M3u8EncryptKey {
             ty: KeyType::None,
-            iv: None,
             content: vec![],
         }
     }
 }
 
 impl M3u8EncryptKey {
-    pub async fn from_key(base_url: &Url, k: &m3u8Key) -> anyhow::Result<Self> {
-        let key_url = base_url.join(k.uri.as_ref().unwrap()).unwrap();
+    pub async fn from_key(key_url: &Url, k: &m3u8Key) -> anyhow::Result<Self> {
+        // 只支持明文下发的密钥，FairPlay 等 DRM 密钥格式无法解密
+        if let Some(keyformat) = &k.keyformat {
+            if keyformat != "identity" {
+                return Err(Error::msg(format!("不支持的密钥格式: {}", keyformat)));
+            }
+        }
         Ok(match k.method {
             KeyMethod::None => M3u8EncryptKey {
                 ty: KeyType::None,
-                iv: k.iv.clone(),
                 content: vec![],
             },
             KeyMethod::AES128 => M3u8EncryptKey {
                 ty: KeyType::Aes128,
-                iv: k.iv.clone(),
-                content: download_request(&key_url).await?,
+                content: download_key(key_url).await?,
             },
             KeyMethod::SampleAES => M3u8EncryptKey {
                 ty: KeyType::SampleAES,
-                iv: k.iv.clone(),
-                content: download_request(&key_url).await?,
+                content: download_key(key_url).await?,
             },
-            _ => panic!("{}", format!("Unsupported key method: {}", &k.method)),
+            _ => return Err(Error::msg(format!("不支持的加密方式: {}", &k.method))),
         })
     }
 
-    pub fn decode(&self, data: &[u8]) -> anyhow::Result<Option<Vec<u8>>, Error> {
-        if self.content.len() == 0 {
+    pub fn decode(&self, data: &[u8], iv: Option<&str>) -> anyhow::Result<Option<Vec<u8>>, Error> {
+        if self.content.is_empty() {
             return Ok(None);
         }
-        let cipher_len = data.len();
-        let mut buf = vec![0u8; cipher_len];
-        let m3u8_key: String = String::from_utf8_lossy(&self.content).to_string();
-
-        match &self.iv {
-            Some(iv) => {
-                let pt = Aes128CbcDec::new(
-                    m3u8_key.as_bytes().into(),
-                    hex::decode(get_hex(&iv)).unwrap().as_bytes().into(),
-                )
-                .decrypt_padded_b2b_mut::<Pkcs7>(data, &mut buf);
-                match pt {
+        let (key, iv) = self.key_iv(iv)?;
+
+        match self.ty {
+            KeyType::None => Ok(None),
+            KeyType::Aes128 => {
+                let mut buf = vec![0u8; data.len()];
+                match Aes128CbcDec::new(&key.into(), &iv.into())
+                    .decrypt_padded_b2b_mut::<Pkcs7>(data, &mut buf)
+                {
                     Ok(pt) => Ok(Some(pt.to_vec())),
                     Err(_) => Err(Error::msg("Decrypt failed")),
                 }
             }
-            _none => {
-                let iv = "0x00000000000000000000000000000000".to_owned();
-                let pt = Aes128CbcDec::new(
-                    m3u8_key.as_bytes().into(),
-                    hex::decode(get_hex(&iv)).unwrap().as_bytes().into(),
-                )
-                .decrypt_padded_b2b_mut::<Pkcs7>(data, &mut buf);
-                match pt {
-                    Ok(pt) => Ok(Some(pt.to_vec())),
+            KeyType::SampleAES => Ok(Some(decrypt_ts(data, &key, &iv)?)),
+        }
+    }
+
+    /// 创建分片解密器，下载过程中按块解密
+    pub fn decryptor(&self, iv: Option<&str>) -> anyhow::Result<SegmentDecryptor> {
+        if self.ty == KeyType::None {
+            return Ok(SegmentDecryptor::Plain);
+        }
+        if self.content.is_empty() {
+            return Err(Error::msg("密钥为空"));
+        }
+        let (key, iv) = self.key_iv(iv)?;
+        Ok(match self.ty {
+            KeyType::Aes128 => SegmentDecryptor::Aes128 {
+                cipher: Box::new(Aes128CbcDec::new(&key.into(), &iv.into())),
+                pending: Vec::new(),
+            },
+            _ => SegmentDecryptor::SampleAes {
+                key,
+                iv,
+                data: Vec::new(),
+            },
+        })
+    }
+
+    fn key_iv(&self, iv: Option<&str>) -> anyhow::Result<([u8; 16], [u8; 16])> {
+        // 密钥是 16 字节的二进制数据，不能按字符串处理
+        let key: [u8; 16] = self
+            .content
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::msg(format!("密钥长度错误: {}", self.content.len())))?;
+        let iv = match iv {
+            Some(iv) => parse_iv(iv)?,
+            _none => [0u8; 16],
+        };
+        Ok((key, iv))
+    }
+}
+
+/// 分片的流式解密器，update 返回已经可以写入文件的明文，finish 返回剩余的明文
+pub enum SegmentDecryptor {
+    Plain,
+    // 最后一个块包含填充，保留到 finish 时再去除
+    Aes128 {
+        cipher: Box<Aes128CbcDec>,
+        pending: Vec<u8>,
+    },
+    // SAMPLE-AES 需要解析完整的 PES 包，整个分片下载完成后再解密
+    SampleAes {
+        key: [u8; 16],
+        iv: [u8; 16],
+        data: Vec<u8>,
+    },
+}
+
+impl SegmentDecryptor {
+    pub fn update(&mut self, chunk: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            SegmentDecryptor::Plain => Ok(chunk.to_vec()),
+            SegmentDecryptor::Aes128 { cipher, pending } => {
+                pending.extend_from_slice(chunk);
+                let keep = match pending.len() % AES_BLOCK_SIZE {
+                    0 => AES_BLOCK_SIZE,
+                    n => n,
+                };
+                let ready = pending.len().saturating_sub(keep);
+                let mut plain: Vec<u8> = pending.drain(..ready).collect();
+                for block in plain.chunks_exact_mut(AES_BLOCK_SIZE) {
+                    cipher.decrypt_block_mut(block.into());
+                }
+                Ok(plain)
+            }
+            SegmentDecryptor::SampleAes { data, .. } => {
+                data.extend_from_slice(chunk);
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    pub fn finish(self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            SegmentDecryptor::Plain => Ok(Vec::new()),
+            SegmentDecryptor::Aes128 {
+                cipher,
+                mut pending,
+            } => {
+                if pending.is_empty() {
+                    return Ok(pending);
+                }
+                if pending.len() != AES_BLOCK_SIZE {
+                    return Err(Error::msg("Decrypt failed"));
+                }
+                match cipher.decrypt_padded_mut::<Pkcs7>(&mut pending) {
+                    Ok(pt) => Ok(pt.to_vec()),
                     Err(_) => Err(Error::msg("Decrypt failed")),
                 }
             }
+            SegmentDecryptor::SampleAes { key, iv, data } => {
+                if data.is_empty() {
+                    return Ok(data);
+                }
+                decrypt_ts(&data, &key, &iv)
+            }
         }
     }
 }
 
+// 未指定 IV 时，使用分片的媒体序列号作为 IV（128 位大端）
+pub fn sequence_iv(media_sequence: u64) -> String {
+    format!("0x{:032x}", media_sequence)
+}
+
+fn parse_iv(iv: &str) -> anyhow::Result<[u8; 16]> {
+    let hex_str = get_hex(iv);
+    // 部分播放列表省略了前导 0
+    let hex_str = format!("{:0>32}", hex_str);
+    hex::decode(&hex_str)?
+        .try_into()
+        .map_err(|_| Error::msg(format!("IV 格式错误: {}", iv)))
+}
+
+// 密钥地址相对于播放列表地址解析
+pub fn key_url(base_url: &Url, k: &m3u8Key) -> anyhow::Result<Url> {
+    let uri = k
+        .uri
+        .as_ref()
+        .ok_or_else(|| Error::msg("EXT-X-KEY 缺少 URI"))?;
+    Ok(base_url.join(uri)?)
+}
+
+async fn download_key(key_url: &Url) -> anyhow::Result<Vec<u8>> {
+    if let Some(content) = KEY_CACHE.get(key_url.as_str()) {
+        return Ok(content);
+    }
+    let content = download_request(key_url).await?;
+    KEY_CACHE.insert(key_url.to_string(), content.clone());
+    Ok(content)
+}
+
 fn get_hex(s: &str) -> &str {
     let (i, _) = parser_hex(s).unwrap_or((s, ""));
     i
 }
 
 fn parser_hex(s: &str) -> IResult<&str, &str> {
-    tag("0x")(s)
+    alt((tag("0x"), tag("0X"))).parse(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aes128_key(key: &str) -> M3u8EncryptKey {
+        M3u8EncryptKey {
+            ty: KeyType::Aes128,
+            content: hex::decode(key).unwrap(),
+        }
+    }
+
+    #[test]
+    fn decode_with_explicit_iv() {
+        let key = aes128_key("000102030405060708090a0b0c0d0e0f");
+        let data = hex::decode("76e4351141f8dc99f065b2fab3c993277def0db290a26f1a9b4b8edc9d66ddef")
+            .unwrap();
+        let pt = key
+            .decode(&data, Some("0x0F0E0D0C0B0A09080706050403020100"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(pt, b"hello HLS segment");
+    }
+
+    #[test]
+    fn decode_with_binary_key_and_sequence_iv() {
+        // 密钥包含非 UTF-8 字节
+        let key = aes128_key("ff80fe7f00c3d9e2a1b2c3d4e5f60718");
+        let data = hex::decode("b691685a48c41f65d5bad666c7bb3e74f6d76931cd160b946ef79f92b5ab45e6")
+            .unwrap();
+        let iv = sequence_iv(7);
+        assert_eq!(iv, "0x00000000000000000000000000000007");
+        let pt = key.decode(&data, Some(&iv)).unwrap().unwrap();
+        assert_eq!(pt, b"\x47\x40\x00\x10media sequence iv test");
+    }
+
+    #[test]
+    fn decryptor_matches_decode_in_chunks() {
+        let key = aes128_key("ff80fe7f00c3d9e2a1b2c3d4e5f60718");
+        let data = hex::decode("b691685a48c41f65d5bad666c7bb3e74f6d76931cd160b946ef79f92b5ab45e6")
+            .unwrap();
+        let iv = sequence_iv(7);
+        let mut decryptor = key.decryptor(Some(&iv)).unwrap();
+        let mut pt = Vec::new();
+        for chunk in data.chunks(5) {
+            pt.extend(decryptor.update(chunk).unwrap());
+        }
+        pt.extend(decryptor.finish().unwrap());
+        assert_eq!(pt, key.decode(&data, Some(&iv)).unwrap().unwrap());
+    }
+
+    #[test]
+    fn parse_short_iv() {
+        assert_eq!(
+            parse_iv("0x7").unwrap(),
+            hex::decode(&sequence_iv(7)[2..]).unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn decode_rejects_invalid_key_length() {
+        let key = M3u8EncryptKey {
+            ty: KeyType::Aes128,
+            content: b"not a key".to_vec(),
+        };
+        assert!(key.decode(&[0u8; 16], None).is_err());
+    }
 }
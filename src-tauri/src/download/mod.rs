@@ -1,5 +1,16 @@
+pub mod ad_filter;
+pub mod bandwidth;
+mod control;
+mod direct_download;
+mod event;
 pub mod file_download;
+mod http_client;
+mod limiter;
+pub mod progress;
 pub mod m3u8_download;
 mod m3u8_encrypt_key;
+mod sample_aes;
+pub mod scheduler;
+pub mod site_headers;
 mod types;
-pub mod util;
+pub mod util;
\ No newline at end of file
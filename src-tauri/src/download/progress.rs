@@ -0,0 +1,172 @@
+use std::time::Instant;
+
+// 本次下载完成的分片数达到该值后，按实际分片大小估算总大小
+const ESTIMATE_MIN_SEGMENTS: u64 = 5;
+
+/// 统计下载的字节数、速度和剩余时间，每次发送进度时采样一次
+pub struct ProgressMeter {
+    start: Instant,
+    last: Instant,
+    // 之前已经下载的字节数，恢复下载时从数据库读取
+    base_bytes: u64,
+    // 上次采样时本次下载的字节数
+    last_bytes: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProgressSample {
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+    // 速度单位字节每秒
+    pub speed: u64,
+    pub average_speed: u64,
+    // 预计剩余时间，单位秒
+    pub eta: Option<u64>,
+}
+
+impl ProgressMeter {
+    pub fn new(base_bytes: u64) -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            base_bytes,
+            last_bytes: 0,
+        }
+    }
+
+    /// session_bytes 为本次下载的字节数，total_bytes 为估算的总大小
+    pub fn sample(&mut self, session_bytes: u64, total_bytes: Option<u64>) -> ProgressSample {
+        let now = Instant::now();
+        let interval = now.duration_since(self.last).as_secs_f64();
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        let speed = if interval > 0.0 {
+            (session_bytes.saturating_sub(self.last_bytes) as f64 / interval) as u64
+        } else {
+            0
+        };
+        let average_speed = if elapsed > 0.0 {
+            (session_bytes as f64 / elapsed) as u64
+        } else {
+            0
+        };
+        self.last = now;
+        self.last_bytes = session_bytes;
+
+        let downloaded_bytes = self.base_bytes + session_bytes;
+        // 总大小不会小于已下载的字节数
+        let total_bytes = total_bytes.map(|total| total.max(downloaded_bytes));
+        // 使用平均速度计算剩余时间，避免瞬时速度波动
+        let eta = match total_bytes {
+            Some(total) if average_speed > 0 => {
+                Some((total - downloaded_bytes).div_ceil(average_speed))
+            }
+            _ => None,
+        };
+        ProgressSample {
+            downloaded_bytes,
+            total_bytes,
+            speed,
+            average_speed,
+            eta,
+        }
+    }
+}
+
+/// 估算分片资源的总字节数
+///
+/// 刚开始下载时使用媒体流码率 × 时长，完成足够多的分片后改用本次下载的平均分片大小，
+/// finished 为 (本次下载的字节数, 本次完成的分片数)
+pub fn estimate_total_bytes(
+    downloaded_bytes: u64,
+    finished: (u64, u64),
+    remaining_segments: u64,
+    nominal_bytes: Option<u64>,
+) -> Option<u64> {
+    let (session_bytes, session_segments) = finished;
+    match nominal_bytes {
+        Some(nominal) if session_segments < ESTIMATE_MIN_SEGMENTS => Some(nominal),
+        _ if session_segments > 0 => {
+            Some(downloaded_bytes + session_bytes / session_segments * remaining_segments)
+        }
+        nominal => nominal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    // 把开始和上次采样时间提前，得到稳定的速度
+    fn meter_started(base_bytes: u64, seconds_ago: u64) -> ProgressMeter {
+        let mut meter = ProgressMeter::new(base_bytes);
+        let start = Instant::now() - Duration::from_secs(seconds_ago);
+        meter.start = start;
+        meter.last = start;
+        meter
+    }
+
+    #[test]
+    fn estimate_uses_nominal_size_before_enough_segments() {
+        let nominal = Some(100_000_000);
+        assert_eq!(estimate_total_bytes(0, (0, 0), 100, nominal), nominal);
+        assert_eq!(
+            estimate_total_bytes(4_000, (4_000, 4), 96, nominal),
+            nominal
+        );
+        assert_eq!(estimate_total_bytes(0, (0, 0), 100, None), None);
+    }
+
+    #[test]
+    fn estimate_extrapolates_from_finished_segments() {
+        // 恢复下载前已有 2000 字节，本次 5 个分片共 5000 字节，剩余 10 个分片
+        assert_eq!(
+            estimate_total_bytes(7_000, (5_000, 5), 10, Some(100_000_000)),
+            Some(17_000)
+        );
+        // 没有码率时从第一个分片开始估算
+        assert_eq!(
+            estimate_total_bytes(3_000, (3_000, 2), 8, None),
+            Some(15_000)
+        );
+    }
+
+    #[test]
+    fn sample_reports_speed_and_eta() {
+        let mut meter = meter_started(1_000, 10);
+        let sample = meter.sample(1_000, Some(12_000));
+        assert_eq!(sample.downloaded_bytes, 2_000);
+        assert_eq!(sample.total_bytes, Some(12_000));
+        assert!((95..=100).contains(&sample.average_speed));
+        assert!((95..=100).contains(&sample.speed));
+        // 剩余 10000 字节，平均速度约 100 字节每秒
+        assert!((100..=106).contains(&sample.eta.unwrap()));
+    }
+
+    #[test]
+    fn sample_without_speed_has_no_eta() {
+        let mut meter = meter_started(5_000, 10);
+        let sample = meter.sample(0, Some(10_000));
+        assert_eq!(sample.average_speed, 0);
+        assert_eq!(sample.eta, None);
+        // 总大小未知时没有剩余时间
+        let mut meter = meter_started(0, 10);
+        let sample = meter.sample(1_000, None);
+        assert!(sample.average_speed > 0);
+        assert_eq!(sample.eta, None);
+    }
+
+    #[test]
+    fn sample_at_completion() {
+        let mut meter = meter_started(0, 10);
+        let sample = meter.sample(10_000, Some(10_000));
+        assert_eq!(sample.eta, Some(0));
+        // 估算的总大小偏小时不小于已下载的字节数
+        let mut meter = meter_started(0, 10);
+        let sample = meter.sample(12_000, Some(10_000));
+        assert_eq!(sample.total_bytes, Some(12_000));
+        assert_eq!(sample.eta, Some(0));
+    }
+}
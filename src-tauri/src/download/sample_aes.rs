@@ -0,0 +1,767 @@
+use std::collections::HashMap;
+
+use aes::cipher::{generic_array::GenericArray, BlockDecryptMut, KeyIvInit};
+use anyhow::{bail, Result};
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_PAYLOAD_SIZE: usize = 184;
+
+// SAMPLE-AES 加密流在 PMT 中的 stream_type 以及解密后对应的明文类型
+const STREAM_TYPE_H264_ENCRYPTED: u8 = 0xdb;
+const STREAM_TYPE_AAC_ENCRYPTED: u8 = 0xcf;
+const STREAM_TYPE_AC3_ENCRYPTED: u8 = 0xc1;
+const STREAM_TYPE_EAC3_ENCRYPTED: u8 = 0xc2;
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_AAC: u8 = 0x0f;
+const STREAM_TYPE_AC3: u8 = 0x81;
+const STREAM_TYPE_EAC3: u8 = 0x87;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Codec {
+    H264,
+    Aac,
+    Ac3,
+}
+
+fn encrypted_codec(stream_type: u8) -> Option<(Codec, u8)> {
+    match stream_type {
+        STREAM_TYPE_H264_ENCRYPTED => Some((Codec::H264, STREAM_TYPE_H264)),
+        STREAM_TYPE_AAC_ENCRYPTED => Some((Codec::Aac, STREAM_TYPE_AAC)),
+        STREAM_TYPE_AC3_ENCRYPTED => Some((Codec::Ac3, STREAM_TYPE_AC3)),
+        STREAM_TYPE_EAC3_ENCRYPTED => Some((Codec::Ac3, STREAM_TYPE_EAC3)),
+        _ => None,
+    }
+}
+
+// 部分片源没有改写 stream_type，此时按明文类型识别加密流
+fn plain_codec(stream_type: u8) -> Option<Codec> {
+    match stream_type {
+        STREAM_TYPE_H264 => Some(Codec::H264),
+        STREAM_TYPE_AAC => Some(Codec::Aac),
+        STREAM_TYPE_AC3 | STREAM_TYPE_EAC3 => Some(Codec::Ac3),
+        _ => None,
+    }
+}
+
+struct TsPacket<'a> {
+    pid: u16,
+    pusi: bool,
+    cc: u8,
+    // 自适应字段内容，不含长度字节
+    adaptation: Option<&'a [u8]>,
+    payload: &'a [u8],
+}
+
+fn parse_packet(packet: &[u8]) -> Result<TsPacket<'_>> {
+    if packet.len() != TS_PACKET_SIZE || packet[0] != 0x47 {
+        bail!("TS 同步字节错误");
+    }
+    let pid = (((packet[1] & 0x1f) as u16) << 8) | packet[2] as u16;
+    let pusi = packet[1] & 0x40 != 0;
+    let afc = (packet[3] >> 4) & 0x03;
+    let cc = packet[3] & 0x0f;
+    let mut offset = 4;
+    let mut adaptation = None;
+    if afc & 0x02 != 0 {
+        let af_len = packet[4] as usize;
+        if 5 + af_len > TS_PACKET_SIZE {
+            bail!("TS 自适应字段长度错误");
+        }
+        adaptation = Some(&packet[5..5 + af_len]);
+        offset = 5 + af_len;
+    }
+    let payload = if afc & 0x01 != 0 {
+        &packet[offset..]
+    } else {
+        &packet[TS_PACKET_SIZE..]
+    };
+    Ok(TsPacket {
+        pid,
+        pusi,
+        cc,
+        adaptation,
+        payload,
+    })
+}
+
+// 取出 PSI 分段（跳过 pointer_field），返回完整的 section 字节
+fn psi_section(packet: &TsPacket) -> Option<(usize, usize)> {
+    if !packet.pusi || packet.payload.is_empty() {
+        return None;
+    }
+    let start = 1 + packet.payload[0] as usize;
+    if start + 3 > packet.payload.len() {
+        return None;
+    }
+    let section_length =
+        (((packet.payload[start + 1] & 0x0f) as usize) << 8) | packet.payload[start + 2] as usize;
+    let end = start + 3 + section_length;
+    if end > packet.payload.len() {
+        return None;
+    }
+    Some((start, end))
+}
+
+fn parse_pat(packet: &TsPacket) -> Vec<u16> {
+    let mut pmt_pids = vec![];
+    if let Some((start, end)) = psi_section(packet) {
+        let section = &packet.payload[start..end];
+        if section[0] != 0x00 || section.len() < 12 {
+            return pmt_pids;
+        }
+        // 跳过 8 字节表头，末尾 4 字节为 CRC
+        for program in section[8..section.len() - 4].chunks_exact(4) {
+            let program_number = ((program[0] as u16) << 8) | program[1] as u16;
+            if program_number != 0 {
+                pmt_pids.push((((program[2] & 0x1f) as u16) << 8) | program[3] as u16);
+            }
+        }
+    }
+    pmt_pids
+}
+
+// 返回 (elementary_PID, stream_type, stream_type 在 section 中的偏移)
+fn parse_pmt(section: &[u8]) -> Vec<(u16, u8, usize)> {
+    let mut streams = vec![];
+    if section.len() < 16 || section[0] != 0x02 {
+        return streams;
+    }
+    let program_info_length = (((section[10] & 0x0f) as usize) << 8) | section[11] as usize;
+    let mut pos = 12 + program_info_length;
+    let end = section.len() - 4;
+    while pos + 5 <= end {
+        let stream_type = section[pos];
+        let pid = (((section[pos + 1] & 0x1f) as u16) << 8) | section[pos + 2] as u16;
+        let es_info_length =
+            (((section[pos + 3] & 0x0f) as usize) << 8) | section[pos + 4] as usize;
+        streams.push((pid, stream_type, pos));
+        pos += 5 + es_info_length;
+    }
+    streams
+}
+
+/// 按 PMT 顺序返回音频流是否为 ADTS 封装的 AAC，合并时只对这些流使用 aac_adtstoasc，
+/// 没有 PMT 或存在无法判断是否为音频的私有流时返回 None
+pub fn ts_audio_streams(data: &[u8]) -> Option<Vec<bool>> {
+    let mut pmt_pids = vec![];
+    for packet in data.chunks_exact(TS_PACKET_SIZE) {
+        let Ok(packet) = parse_packet(packet) else {
+            continue;
+        };
+        if packet.pid == 0 {
+            pmt_pids.extend(parse_pat(&packet));
+        } else if pmt_pids.contains(&packet.pid) {
+            let (start, end) = psi_section(&packet)?;
+            let mut streams = vec![];
+            for (_, stream_type, _) in parse_pmt(&packet.payload[start..end]) {
+                match stream_type {
+                    STREAM_TYPE_AAC | STREAM_TYPE_AAC_ENCRYPTED => streams.push(true),
+                    // MP3、LATM 封装的 AAC、AC-3、E-AC-3
+                    0x03
+                    | 0x04
+                    | 0x11
+                    | STREAM_TYPE_AC3
+                    | STREAM_TYPE_EAC3
+                    | STREAM_TYPE_AC3_ENCRYPTED
+                    | STREAM_TYPE_EAC3_ENCRYPTED => streams.push(false),
+                    // PES 私有数据和其他私有类型可能是音频也可能是字幕、数据流
+                    0x06 | 0x80 | 0x82..=0x85 | 0x88..=0xff
+                        if stream_type != STREAM_TYPE_H264_ENCRYPTED =>
+                    {
+                        return None
+                    }
+                    _ => {}
+                }
+            }
+            return Some(streams);
+        }
+    }
+    None
+}
+
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+struct PesBuffer {
+    cc: u8,
+    adaptation: Option<Vec<u8>>,
+    data: Vec<u8>,
+}
+
+/// 解密 SAMPLE-AES 加密的 MPEG-TS 分片，H.264 按 NAL 单元、AAC/AC-3 按音频帧解密，
+/// 并把 PMT 中的加密流类型改回明文类型，返回可直接合并的 TS 数据
+pub fn decrypt_ts(data: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Result<Vec<u8>> {
+    if data.len() < TS_PACKET_SIZE || data[0] != 0x47 {
+        bail!("SAMPLE-AES 仅支持 MPEG-TS 分片");
+    }
+    let packets = data
+        .chunks(TS_PACKET_SIZE)
+        .filter(|chunk| chunk.len() == TS_PACKET_SIZE)
+        .map(parse_packet)
+        .collect::<Result<Vec<_>>>()?;
+
+    // 第一遍：从 PAT/PMT 中找出需要解密的流
+    let mut pmt_pids = vec![];
+    let mut pmt_streams = vec![];
+    for packet in &packets {
+        if packet.pid == 0 {
+            pmt_pids.extend(parse_pat(packet));
+        } else if pmt_pids.contains(&packet.pid) {
+            if let Some((start, end)) = psi_section(packet) {
+                pmt_streams.extend(parse_pmt(&packet.payload[start..end]));
+            }
+        }
+    }
+    let mut codecs: HashMap<u16, Codec> = pmt_streams
+        .iter()
+        .filter_map(|(pid, stream_type, _)| encrypted_codec(*stream_type).map(|(c, _)| (*pid, c)))
+        .collect();
+    if codecs.is_empty() {
+        codecs = pmt_streams
+            .iter()
+            .filter_map(|(pid, stream_type, _)| plain_codec(*stream_type).map(|c| (*pid, c)))
+            .collect();
+    }
+
+    // 第二遍：重组 PES 并解密，其余数据原样输出
+    let mut out = Vec::with_capacity(data.len());
+    let mut pes_buffers: HashMap<u16, PesBuffer> = HashMap::new();
+    let mut continuity: HashMap<u16, u8> = HashMap::new();
+    for (index, packet) in packets.iter().enumerate() {
+        let raw = &data[index * TS_PACKET_SIZE..(index + 1) * TS_PACKET_SIZE];
+        if let Some(codec) = codecs.get(&packet.pid) {
+            if packet.pusi {
+                if let Some(pes) = pes_buffers.remove(&packet.pid) {
+                    flush_pes(packet.pid, *codec, pes, key, iv, &mut continuity, &mut out)?;
+                }
+                pes_buffers.insert(
+                    packet.pid,
+                    PesBuffer {
+                        cc: packet.cc,
+                        adaptation: packet.adaptation.map(strip_stuffing),
+                        data: packet.payload.to_vec(),
+                    },
+                );
+            } else if packet.payload.is_empty() {
+                // 只携带 PCR 等自适应字段的包原样保留
+                out.extend_from_slice(raw);
+            } else if let Some(pes) = pes_buffers.get_mut(&packet.pid) {
+                pes.data.extend_from_slice(packet.payload);
+            }
+        } else if pmt_pids.contains(&packet.pid) {
+            out.extend_from_slice(&rewrite_pmt(raw, packet)?);
+        } else {
+            out.extend_from_slice(raw);
+        }
+    }
+    for (pid, pes) in pes_buffers {
+        flush_pes(pid, codecs[&pid], pes, key, iv, &mut continuity, &mut out)?;
+    }
+    Ok(out)
+}
+
+fn rewrite_pmt(raw: &[u8], packet: &TsPacket) -> Result<Vec<u8>> {
+    let mut rewritten = raw.to_vec();
+    let Some((start, end)) = psi_section(packet) else {
+        return Ok(rewritten);
+    };
+    let payload_offset = TS_PACKET_SIZE - packet.payload.len();
+    let section_offset = payload_offset + start;
+    let section_end = payload_offset + end;
+    let mut changed = false;
+    for (_, stream_type, pos) in parse_pmt(&packet.payload[start..end]) {
+        if let Some((_, plain)) = encrypted_codec(stream_type) {
+            rewritten[section_offset + pos] = plain;
+            changed = true;
+        }
+    }
+    if changed {
+        let crc = crc32_mpeg2(&rewritten[section_offset..section_end - 4]);
+        rewritten[section_end - 4..section_end].copy_from_slice(&crc.to_be_bytes());
+    }
+    Ok(rewritten)
+}
+
+fn flush_pes(
+    pid: u16,
+    codec: Codec,
+    mut pes: PesBuffer,
+    key: &[u8; 16],
+    iv: &[u8; 16],
+    continuity: &mut HashMap<u16, u8>,
+    out: &mut Vec<u8>,
+) -> Result<()> {
+    let data = &mut pes.data;
+    if data.len() >= 9 && data[0..3] == [0x00, 0x00, 0x01] {
+        let header_len = 9 + data[8] as usize;
+        if header_len <= data.len() {
+            let payload = &data[header_len..];
+            let decrypted = match codec {
+                Codec::H264 => decrypt_h264(payload, key, iv),
+                Codec::Aac => decrypt_aac(payload, key, iv),
+                Codec::Ac3 => decrypt_ac3(payload, key, iv),
+            };
+            data.truncate(header_len);
+            data.extend_from_slice(&decrypted);
+            // 视频 NAL 重新插入防竞争字节后长度可能变化
+            let pes_packet_length = data[4..6]
+                .iter()
+                .fold(0usize, |a, b| (a << 8) | *b as usize);
+            if pes_packet_length != 0 {
+                let new_length = data.len() - 6;
+                let new_length = if new_length > 0xffff { 0 } else { new_length };
+                data[4..6].copy_from_slice(&(new_length as u16).to_be_bytes());
+            }
+        }
+    }
+    let cc = continuity.entry(pid).or_insert(pes.cc);
+    write_packets(pid, cc, pes.adaptation, data, out);
+    Ok(())
+}
+
+// 去掉自适应字段末尾的填充字节，重新打包时按需要另行填充
+fn strip_stuffing(adaptation: &[u8]) -> Vec<u8> {
+    let Some(flags) = adaptation.first() else {
+        return vec![];
+    };
+    let mut len = 1;
+    if flags & 0x10 != 0 {
+        len += 6;
+    }
+    if flags & 0x08 != 0 {
+        len += 6;
+    }
+    if flags & 0x04 != 0 {
+        len += 1;
+    }
+    if flags & 0x02 != 0 {
+        len += 1 + adaptation.get(len).copied().unwrap_or(0) as usize;
+    }
+    if flags & 0x01 != 0 {
+        len += 1 + adaptation.get(len).copied().unwrap_or(0) as usize;
+    }
+    adaptation[..len.min(adaptation.len())].to_vec()
+}
+
+fn write_packets(
+    pid: u16,
+    cc: &mut u8,
+    adaptation: Option<Vec<u8>>,
+    data: &[u8],
+    out: &mut Vec<u8>,
+) {
+    let mut pos = 0;
+    let mut first = true;
+    while first || pos < data.len() {
+        let mut adaptation = if first { adaptation.clone() } else { None };
+        let adaptation_size = adaptation.as_ref().map_or(0, |a| a.len() + 1);
+        let mut size = TS_PAYLOAD_SIZE - adaptation_size;
+        let remaining = data.len() - pos;
+        if remaining < size {
+            // 最后一个包用自适应字段填充
+            let stuffing = size - remaining;
+            let a = adaptation.get_or_insert_with(Vec::new);
+            let stuffing = if adaptation_size == 0 {
+                stuffing - 1
+            } else {
+                stuffing
+            };
+            if stuffing > 0 {
+                if a.is_empty() {
+                    a.push(0x00);
+                    a.resize(stuffing, 0xff);
+                } else {
+                    a.resize(a.len() + stuffing, 0xff);
+                }
+            }
+            size = remaining;
+        }
+        out.push(0x47);
+        out.push(if first { 0x40 } else { 0x00 } | ((pid >> 8) as u8 & 0x1f));
+        out.push(pid as u8);
+        let afc = if adaptation.is_some() { 0x30 } else { 0x10 };
+        out.push(afc | *cc);
+        if let Some(a) = &adaptation {
+            out.push(a.len() as u8);
+            out.extend_from_slice(a);
+        }
+        out.extend_from_slice(&data[pos..pos + size]);
+        pos += size;
+        *cc = (*cc + 1) & 0x0f;
+        first = false;
+    }
+}
+
+fn decrypt_blocks(
+    data: &mut [u8],
+    key: &[u8; 16],
+    iv: &[u8; 16],
+    offsets: impl Iterator<Item = usize>,
+) {
+    let mut cipher = Aes128CbcDec::new(key.into(), iv.into());
+    for offset in offsets {
+        cipher.decrypt_block_mut(GenericArray::from_mut_slice(&mut data[offset..offset + 16]));
+    }
+}
+
+fn decrypt_h264(payload: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len());
+    let starts = start_codes(payload);
+    if starts.is_empty() {
+        return payload.to_vec();
+    }
+    out.extend_from_slice(&payload[..starts[0]]);
+    for (i, start) in starts.iter().enumerate() {
+        let nal_start = start + 3;
+        let next = starts.get(i + 1).copied().unwrap_or(payload.len());
+        // 下一个起始码前的 0 属于 4 字节起始码或尾部填充
+        let mut nal_end = next;
+        while nal_end > nal_start && payload[nal_end - 1] == 0 {
+            nal_end -= 1;
+        }
+        out.extend_from_slice(&payload[*start..nal_start]);
+        let nal = &payload[nal_start..nal_end];
+        let nal_type = nal.first().map_or(0, |b| b & 0x1f);
+        // 只有长度大于 48 字节的 slice（类型 1、5）被加密
+        if (nal_type == 1 || nal_type == 5) && nal.len() > 48 {
+            let mut unescaped = remove_emulation_prevention(nal);
+            let len = unescaped.len();
+            // 前 32 字节明文，之后每 160 字节中加密前 16 字节，不足 16 字节的尾部不加密
+            decrypt_blocks(
+                &mut unescaped,
+                key,
+                iv,
+                (32..len.saturating_sub(16)).step_by(160),
+            );
+            out.extend_from_slice(&add_emulation_prevention(&unescaped));
+        } else {
+            out.extend_from_slice(nal);
+        }
+        out.extend_from_slice(&payload[nal_end..next]);
+    }
+    out
+}
+
+fn start_codes(data: &[u8]) -> Vec<usize> {
+    let mut starts = vec![];
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            starts.push(i);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    starts
+}
+
+fn remove_emulation_prevention(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    out
+}
+
+fn add_emulation_prevention(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len() + nal.len() / 64);
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b <= 0x03 {
+            out.push(0x03);
+            zeros = 0;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    out
+}
+
+fn decrypt_aac(payload: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    let mut out = payload.to_vec();
+    let mut pos = 0;
+    while pos + 7 <= out.len() {
+        if out[pos] != 0xff || out[pos + 1] & 0xf0 != 0xf0 {
+            break;
+        }
+        let header_len = if out[pos + 1] & 0x01 != 0 { 7 } else { 9 };
+        let frame_len = (((out[pos + 3] & 0x03) as usize) << 11)
+            | ((out[pos + 4] as usize) << 3)
+            | ((out[pos + 5] as usize) >> 5);
+        if frame_len < header_len || pos + frame_len > out.len() {
+            break;
+        }
+        // ADTS 头之后 16 字节明文，其后的完整 16 字节块加密
+        let frame = &mut out[pos + header_len..pos + frame_len];
+        decrypt_audio_frame(frame, key, iv);
+        pos += frame_len;
+    }
+    out
+}
+
+fn decrypt_ac3(payload: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    let mut out = payload.to_vec();
+    let mut pos = 0;
+    while pos + 6 <= out.len() {
+        if out[pos] != 0x0b || out[pos + 1] != 0x77 {
+            break;
+        }
+        let frame_len = match ac3_frame_size(&out[pos..]) {
+            Some(len) if len > 0 && pos + len <= out.len() => len,
+            _ => break,
+        };
+        decrypt_audio_frame(&mut out[pos..pos + frame_len], key, iv);
+        pos += frame_len;
+    }
+    out
+}
+
+fn decrypt_audio_frame(frame: &mut [u8], key: &[u8; 16], iv: &[u8; 16]) {
+    let len = frame.len();
+    if len <= 16 {
+        return;
+    }
+    let end = len - (len % 16);
+    decrypt_blocks(frame, key, iv, (16..end).step_by(16));
+}
+
+fn ac3_frame_size(header: &[u8]) -> Option<usize> {
+    const BITRATES: [usize; 19] = [
+        32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512, 576, 640,
+    ];
+    let bsid = header[5] >> 3;
+    if bsid > 10 {
+        // E-AC-3：frmsiz 为 11 位字数减一
+        let words = ((((header[2] & 0x07) as usize) << 8) | header[3] as usize) + 1;
+        return Some(words * 2);
+    }
+    let fscod = header[4] >> 6;
+    let frmsizecod = (header[4] & 0x3f) as usize;
+    let bitrate = *BITRATES.get(frmsizecod / 2)?;
+    let words = match fscod {
+        0 => bitrate * 2,
+        1 => bitrate * 96000 / 44100 + (frmsizecod & 1),
+        2 => bitrate * 3,
+        _ => return None,
+    };
+    Some(words * 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ];
+    const IV: [u8; 16] = [
+        0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e,
+        0x1f,
+    ];
+
+    // 230 字节的 IDR slice，偏移 32 和 192 处的块加密，明文和密文中的防竞争字节位置不同
+    const H264_PLAIN: &str = concat!(
+        "6530557a9f0000030133587da2c7ec11365b80a5caef14395e83a8cdf2173c6186abd0f51a3f6489ae",
+        "0000030142678cb1d6fb20456a8fb4d9fe23486d9200000300264b7095badf04294e7398bde2072c51",
+        "769bc0e50a2f54799ec3e80d32577ca1c6eb10355a00000301ee13385d82a7ccf1163b6085aacff419",
+        "3e6388add2f71c41668bb0d5fa1f44698eb3d8fd22476c91b6db00254a6f94b9de03284d7297bce106",
+        "2b50759abfe4092e53789dc2e70c31567ba0c5ea0f34597ea3c8ed12375c81a6cbf0153a5f84a9ce00",
+        "0003016287acd1f61b40658aafd4f91e43688db2d7fc21466b90b5daff80",
+    );
+    const H264_ENCRYPTED: &str = concat!(
+        "6530557a9f0000030133587da2c7ec11365b80a5caef14395e83a8cdf2173c6186a178b4a10f18c896",
+        "961a968df20f153efb20456a8fb4d9fe23486d9200000300264b7095badf04294e7398bde2072c5176",
+        "9bc0e50a2f54799ec3e80d32577ca1c6eb10355a00000301ee13385d82a7ccf1163b6085aacff4193e",
+        "6388add2f71c41668bb0d5fa1f44698eb3d8fd22476c91b6db00254a6f94b9de03284d7297bce1062b",
+        "50759abfe4092e53789dc2e70c31567ba0c5ea0f34597ea3c8ed12375c81a633470e0c8e2078ebec39",
+        "bcd0c02337581b40658aafd4f91e43688db2d7fc21466b90b5daff80",
+    );
+    // 60 字节的 ADTS 帧，帧头后偏移 16 和 32 处的块加密
+    const ADTS_PLAIN: &str = concat!(
+        "fff15080079ffc073c71a6db10457aafe4194e83b8ed22578cc1f62b6095caff34699ed3083d72a7dc",
+        "11467bb0e51a4f84b9ee23588dc2f72c6196cb",
+    );
+    const ADTS_ENCRYPTED: &str = concat!(
+        "fff15080079ffc073c71a6db10457aafe4194e83b8ed227972a537b283d0681add3bab5c8b05016577",
+        "b5998569794994ceee6f85071f45f72c6196cb",
+    );
+    // 节目 1 的 PMT 指向 0x1000
+    const PAT: &str = "00b00d0001c100000001f0002ab104b2";
+    // 视频 0x100、音频 0x101
+    const PMT_ENCRYPTED: &str = "02b0170001c10000e100f000dbe100f000cfe101f000e4c750ae";
+    const PMT_PLAIN: &str = "02b0170001c10000e100f0001be100f0000fe101f0002f44b99b";
+
+    fn bytes(s: &str) -> Vec<u8> {
+        hex::decode(s).unwrap()
+    }
+
+    // 按 TS 格式打包，PSI 用 0xff 填充负载，PES 用自适应字段填充
+    fn ts_packet(pid: u16, pusi: bool, cc: u8, payload: &[u8], psi: bool) -> Vec<u8> {
+        let mut packet = vec![
+            0x47,
+            if pusi { 0x40 } else { 0x00 } | (pid >> 8) as u8,
+            pid as u8,
+        ];
+        let stuffing = TS_PAYLOAD_SIZE - payload.len();
+        if psi || stuffing == 0 {
+            packet.push(0x10 | cc);
+            packet.extend_from_slice(payload);
+            packet.resize(TS_PACKET_SIZE, 0xff);
+        } else {
+            packet.push(0x30 | cc);
+            packet.push((stuffing - 1) as u8);
+            if stuffing > 1 {
+                packet.push(0x00);
+                packet.resize(4 + stuffing, 0xff);
+            }
+            packet.extend_from_slice(payload);
+        }
+        assert_eq!(packet.len(), TS_PACKET_SIZE);
+        packet
+    }
+
+    fn psi_packet(pid: u16, section: &str) -> Vec<u8> {
+        let mut payload = vec![0x00];
+        payload.extend(bytes(section));
+        ts_packet(pid, true, 0, &payload, true)
+    }
+
+    fn pes(stream_id: u8, data: &[u8]) -> Vec<u8> {
+        let mut pes = vec![0x00, 0x00, 0x01, stream_id, 0x00, 0x00, 0x80, 0x80, 0x05];
+        pes.extend_from_slice(&[0x21, 0x00, 0x01, 0x00, 0x01]);
+        pes.extend_from_slice(data);
+        let length = (pes.len() - 6) as u16;
+        pes[4..6].copy_from_slice(&length.to_be_bytes());
+        pes
+    }
+
+    fn pes_packets(pid: u16, pes: &[u8]) -> Vec<u8> {
+        pes.chunks(TS_PAYLOAD_SIZE)
+            .enumerate()
+            .flat_map(|(i, chunk)| ts_packet(pid, i == 0, i as u8, chunk, false))
+            .collect()
+    }
+
+    fn video_es(nal: &str) -> Vec<u8> {
+        let mut es = vec![0x00, 0x00, 0x00, 0x01, 0x09, 0xf0, 0x00, 0x00, 0x00, 0x01];
+        es.extend(bytes(nal));
+        es
+    }
+
+    #[test]
+    fn crc32_mpeg2_check_value() {
+        assert_eq!(crc32_mpeg2(b"123456789"), 0x0376_e6e7);
+        // 包含 CRC 的完整 section 校验结果为 0
+        assert_eq!(crc32_mpeg2(&bytes(PMT_PLAIN)), 0);
+    }
+
+    #[test]
+    fn decrypt_h264_nal_with_emulation_prevention() {
+        let mut encrypted = vec![0x00, 0x00, 0x00, 0x01];
+        encrypted.extend(bytes(H264_ENCRYPTED));
+        let mut expected = vec![0x00, 0x00, 0x00, 0x01];
+        expected.extend(bytes(H264_PLAIN));
+        assert_eq!(decrypt_h264(&encrypted, &KEY, &IV), expected);
+    }
+
+    #[test]
+    fn decrypt_h264_keeps_short_and_non_slice_nal() {
+        let mut data = vec![0x00, 0x00, 0x01, 0x67];
+        data.extend([0xaa; 60]);
+        data.extend([0x00, 0x00, 0x01, 0x65]);
+        data.extend([0xbb; 40]);
+        assert_eq!(decrypt_h264(&data, &KEY, &IV), data);
+    }
+
+    #[test]
+    fn decrypt_adts_frames() {
+        // 每一帧重新从 IV 开始解密
+        let encrypted = [bytes(ADTS_ENCRYPTED), bytes(ADTS_ENCRYPTED)].concat();
+        let expected = [bytes(ADTS_PLAIN), bytes(ADTS_PLAIN)].concat();
+        assert_eq!(decrypt_aac(&encrypted, &KEY, &IV), expected);
+    }
+
+    #[test]
+    fn rewrite_pmt_stream_types_and_crc() {
+        let data = [psi_packet(0, PAT), psi_packet(0x1000, PMT_ENCRYPTED)].concat();
+        let out = decrypt_ts(&data, &KEY, &IV).unwrap();
+        assert_eq!(out.len(), 2 * TS_PACKET_SIZE);
+        assert_eq!(out[..TS_PACKET_SIZE], data[..TS_PACKET_SIZE]);
+        let pmt = &out[TS_PACKET_SIZE..];
+        let section = bytes(PMT_PLAIN);
+        assert_eq!(pmt[..5], data[TS_PACKET_SIZE..TS_PACKET_SIZE + 5]);
+        assert_eq!(pmt[5..5 + section.len()], section[..]);
+        assert!(pmt[5 + section.len()..].iter().all(|b| *b == 0xff));
+    }
+
+    #[test]
+    fn ts_audio_streams_in_pmt_order() {
+        let data = [psi_packet(0, PAT), psi_packet(0x1000, PMT_PLAIN)].concat();
+        assert_eq!(ts_audio_streams(&data), Some(vec![true]));
+        // 视频、AC-3、AAC
+        let pmt = "02b01c0001c10000e100f0001be100f00081e101f0000fe102f000ffffffff";
+        let data = [psi_packet(0, PAT), psi_packet(0x1000, pmt)].concat();
+        assert_eq!(ts_audio_streams(&data), Some(vec![false, true]));
+        // PES 私有数据无法判断类型
+        let pmt = "02b0170001c10000e100f0001be100f00006e101f000ffffffff";
+        let data = [psi_packet(0, PAT), psi_packet(0x1000, pmt)].concat();
+        assert_eq!(ts_audio_streams(&data), None);
+        assert_eq!(ts_audio_streams(&psi_packet(0, PAT)), None);
+    }
+
+    #[test]
+    fn decrypt_ts_round_trip() {
+        let video = pes(0xe0, &video_es(H264_ENCRYPTED));
+        let audio = pes(0xc0, &bytes(ADTS_ENCRYPTED));
+        let data = [
+            psi_packet(0, PAT),
+            psi_packet(0x1000, PMT_ENCRYPTED),
+            pes_packets(0x100, &video),
+            pes_packets(0x101, &audio),
+        ]
+        .concat();
+        let out = decrypt_ts(&data, &KEY, &IV).unwrap();
+        assert_eq!(out.len() % TS_PACKET_SIZE, 0);
+
+        let mut streams: HashMap<u16, Vec<u8>> = HashMap::new();
+        let mut continuity: HashMap<u16, u8> = HashMap::new();
+        for raw in out.chunks(TS_PACKET_SIZE) {
+            let packet = parse_packet(raw).unwrap();
+            if let Some(cc) = continuity.insert(packet.pid, packet.cc) {
+                assert_eq!(packet.cc, (cc + 1) & 0x0f);
+            }
+            if packet.pid == 0x1000 {
+                let (start, end) = psi_section(&packet).unwrap();
+                assert_eq!(packet.payload[start..end], bytes(PMT_PLAIN)[..]);
+            } else if packet.pid != 0 {
+                let pes = streams.entry(packet.pid).or_default();
+                assert_eq!(packet.pusi, pes.is_empty());
+                pes.extend_from_slice(packet.payload);
+            }
+        }
+        assert_eq!(streams[&0x100], pes(0xe0, &video_es(H264_PLAIN)));
+        assert_eq!(streams[&0x101], pes(0xc0, &bytes(ADTS_PLAIN)));
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::HashSet, sync::Mutex, time::Duration};
+
+use lazy_static::lazy_static;
+use log::{error, info};
+use tokio::{sync::Notify, time};
+
+use crate::{
+    conf::get_string,
+    orm::{
+        download_info::{
+            service::{find_download_by_id, update_download_by_id},
+            types::{DownloadInfo, DownloadInfoUpdate},
+        },
+        download_queue::{
+            service::{
+                enqueue_download, remove_download, reset_running_download, take_next_download,
+            },
+            types::DownloadQueue,
+        },
+    },
+};
+
+use super::{
+    event::publish,
+    file_download::service::download_info_to_download_task_info,
+    m3u8_download::M3u8Download,
+    types::{DownloadEvent, DownloadInfoResponse},
+};
+
+// 默认同时下载的任务数
+const DEFAULT_MAX_TASKS: usize = 2;
+
+lazy_static! {
+    // 新任务入队或任务结束时唤醒调度
+    static ref SCHEDULER_WAKE: Notify = Notify::new();
+    // 正在下载的任务 id
+    static ref RUNNING_DOWNLOADS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+// downloadMaxTasks 同时下载的任务数
+pub fn max_tasks() -> usize {
+    get_string("downloadMaxTasks")
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_MAX_TASKS)
+}
+
+/// 加入持久化的下载队列并唤醒调度，重启应用后仍会继续下载
+pub fn enqueue(download_id: &str, priority: i32) -> anyhow::Result<()> {
+    enqueue_download(download_id, priority)?;
+    SCHEDULER_WAKE.notify_one();
+    Ok(())
+}
+
+/// 从队列中移除等待中的任务，正在下载的任务需要通过暂停或取消停止
+pub fn dequeue(download_id: &str) -> anyhow::Result<()> {
+    if !is_running(download_id) {
+        remove_download(download_id)?;
+    }
+    Ok(())
+}
+
+pub fn is_running(download_id: &str) -> bool {
+    RUNNING_DOWNLOADS.lock().unwrap().contains(download_id)
+}
+
+pub async fn run() {
+    match reset_running_download() {
+        Ok(count) if count > 0 => info!("恢复上次未完成的下载任务, count:{}", count),
+        Err(e) => error!("恢复下载队列失败: {}", e),
+        _ => {}
+    }
+    loop {
+        while RUNNING_DOWNLOADS.lock().unwrap().len() < max_tasks() {
+            match take_next_download() {
+                Ok(Some(download_queue)) => start(download_queue),
+                Ok(None) => break,
+                Err(e) => {
+                    error!("读取下载队列失败: {}", e);
+                    break;
+                }
+            }
+        }
+        // 定时检查使同时下载数的修改生效
+        tokio::select! {
+            _ = SCHEDULER_WAKE.notified() => {}
+            _ = time::sleep(Duration::from_secs(5)) => {}
+        }
+    }
+}
+
+fn start(download_queue: DownloadQueue) {
+    let download_id = download_queue.download_id;
+    let download_info = match find_download_by_id(&download_id) {
+        Ok(Some(download_info)) => download_info,
+        Ok(None) => {
+            // 下载记录已删除
+            let _ = remove_download(&download_id);
+            return;
+        }
+        Err(e) => {
+            error!("查询下载信息失败: {}", e);
+            return;
+        }
+    };
+    // 应用重启后未完成的任务会再次取出，attempt_count 记录每次启动
+    info!(
+        "开始下载, id:{}, attempt:{}",
+        download_id, download_queue.attempt_count
+    );
+    RUNNING_DOWNLOADS
+        .lock()
+        .unwrap()
+        .insert(download_id.clone());
+    // 与共享的 HTTP 客户端、限速和并发唤醒使用同一个运行时
+    tauri::async_runtime::spawn(async move {
+        run_download(download_info).await;
+        RUNNING_DOWNLOADS.lock().unwrap().remove(&download_id);
+        if let Err(e) = remove_download(&download_id) {
+            error!("移除下载队列失败: {}", e);
+        }
+        SCHEDULER_WAKE.notify_one();
+    });
+}
+
+async fn run_download(download_info: DownloadInfo) {
+    let mut download_task_info = download_info_to_download_task_info(download_info);
+    download_task_info.download_status = "downloading".to_string();
+    let download_info_update = DownloadInfoUpdate {
+        id: download_task_info.id.clone(),
+        download_status: Some(download_task_info.download_status.clone()),
+        ..Default::default()
+    };
+    let _ = update_download_by_id(download_info_update);
+    publish(DownloadEvent::StatusChange(DownloadInfoResponse {
+        id: download_task_info.id.clone(),
+        status: download_task_info.status.clone(),
+        download_status: Some(download_task_info.download_status.clone()),
+        ..Default::default()
+    }));
+    // 错误在 await 之前转为字符串，下载任务需要在多线程运行时中执行
+    match M3u8Download::new(&mut download_task_info).map_err(|e| e.to_string()) {
+        Ok(mut m3u8_download) => m3u8_download.start_download().await,
+        Err(e) => {
+            error!("创建视频下载对象失败: {}", e);
+            let download_info_update = DownloadInfoUpdate {
+                id: download_task_info.id.clone(),
+                download_status: Some("downloadFail".to_string()),
+                ..Default::default()
+            };
+            let _ = update_download_by_id(download_info_update);
+            publish(DownloadEvent::End(DownloadInfoResponse {
+                id: download_task_info.id.clone(),
+                status: download_task_info.status.clone(),
+                download_status: Some("downloadFail".to_string()),
+                reason: Some(e),
+                ..Default::default()
+            }));
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use lazy_static::lazy_static;
+use log::error;
+use serde::{Deserialize, Serialize};
+use tauri::http::{HeaderMap, HeaderName, HeaderValue};
+use url::Url;
+
+use crate::orm::site::service::get_all_sites;
+
+lazy_static! {
+    // 所有站点的请求头规则，站点变化后清空，下次请求时重新读取
+    static ref HEADER_RULES: Mutex<Option<Vec<HeaderRule>>> = Mutex::new(None);
+}
+
+/// 站点的请求头规则，host 为 * 时对所有地址生效，*.example.com 只匹配子域名，
+/// example.com 匹配该域名及其子域名
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderRule {
+    pub host: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl HeaderRule {
+    fn matches(&self, host: &str) -> bool {
+        match self.host.as_str() {
+            "*" => true,
+            pattern => match pattern.strip_prefix("*.") {
+                Some(domain) => host.ends_with(&format!(".{}", domain)),
+                None => host == pattern || host.ends_with(&format!(".{}", pattern)),
+            },
+        }
+    }
+}
+
+/// 解析站点保存的请求头规则，空字符串视为没有规则
+pub fn parse_header_rules(headers: &str) -> anyhow::Result<Vec<HeaderRule>> {
+    if headers.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let rules: Vec<HeaderRule> = serde_json::from_str(headers)?;
+    for rule in &rules {
+        for (name, value) in &rule.headers {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| anyhow::anyhow!("请求头名称无效: {}", name))?;
+            HeaderValue::from_str(value)
+                .map_err(|_| anyhow::anyhow!("请求头 {} 的值无效", name))?;
+        }
+    }
+    Ok(rules)
+}
+
+pub fn clear_header_rules() {
+    *HEADER_RULES.lock().unwrap() = None;
+}
+
+fn header_rules() -> Vec<HeaderRule> {
+    let mut rules = HEADER_RULES.lock().unwrap();
+    if rules.is_none() {
+        let sites = get_all_sites().unwrap_or_else(|e| {
+            error!("读取站点请求头失败: {}", e);
+            Vec::new()
+        });
+        let mut loaded = Vec::new();
+        for site in sites {
+            match parse_header_rules(site.headers.as_deref().unwrap_or_default()) {
+                Ok(site_rules) => loaded.extend(site_rules),
+                Err(e) => error!("站点 {} 的请求头格式错误: {}", site.site_key, e),
+            }
+        }
+        *rules = Some(loaded);
+    }
+    rules.clone().unwrap_or_default()
+}
+
+/// 返回匹配地址的请求头，多个规则命中同一个请求头时域名更具体的规则优先
+pub fn site_headers(url: &Url) -> HeaderMap {
+    let host = url.host_str().unwrap_or_default();
+    let mut rules: Vec<HeaderRule> = header_rules()
+        .into_iter()
+        .filter(|r| r.matches(host))
+        .collect();
+    rules.sort_by_key(|r| if r.host == "*" { 0 } else { r.host.len() });
+    let mut headers = HeaderMap::new();
+    for rule in rules {
+        for (name, value) in rule.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(&value),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+    }
+    headers
+}
+
+/// 地址无法解析时返回空的请求头
+pub fn url_site_headers(url: &str) -> HeaderMap {
+    Url::parse(url)
+        .map(|url| site_headers(&url))
+        .unwrap_or_default()
+}
+
+pub mod cmd {
+    use std::collections::HashMap;
+
+    use tauri::command;
+
+    use super::url_site_headers;
+
+    /// 播放时由前端附加到请求上，Referer、User-Agent 等受限的请求头会被 WebView 忽略
+    #[command]
+    pub fn get_site_headers(url: String) -> HashMap<String, String> {
+        url_site_headers(&url)
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(host: &str) -> HeaderRule {
+        HeaderRule {
+            host: host.to_string(),
+            headers: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn matches_wildcard_hosts() {
+        assert!(rule("*").matches("cdn.example.com"));
+        assert!(rule("*.example.com").matches("cdn.example.com"));
+        assert!(rule("*.example.com").matches("a.cdn.example.com"));
+        assert!(!rule("*.example.com").matches("example.com"));
+        assert!(!rule("*.example.com").matches("badexample.com"));
+    }
+
+    #[test]
+    fn matches_domain_and_subdomains() {
+        assert!(rule("example.com").matches("example.com"));
+        assert!(rule("example.com").matches("cdn.example.com"));
+        assert!(!rule("example.com").matches("notexample.com"));
+        assert!(!rule("example.com").matches("example.com.evil.net"));
+    }
+
+    #[test]
+    fn matches_host_of_url_with_port() {
+        let url = Url::parse("https://cdn.example.com:8443/live/index.m3u8").unwrap();
+        let host = url.host_str().unwrap();
+        assert!(rule("example.com").matches(host));
+        assert!(rule("*.example.com").matches(host));
+        assert!(!rule("example.com:8443").matches(host));
+    }
+
+    #[test]
+    fn parse_header_rules_accepts_valid_rules() {
+        assert!(parse_header_rules("  ").unwrap().is_empty());
+        let rules = parse_header_rules(
+            r#"[{"host":"*.example.com","headers":{"Referer":"https://example.com/"}},{"host":"*"}]"#,
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].host, "*.example.com");
+        assert_eq!(rules[0].headers["Referer"], "https://example.com/");
+        assert!(rules[1].headers.is_empty());
+    }
+
+    #[test]
+    fn parse_header_rules_rejects_invalid_headers() {
+        let name = parse_header_rules(r#"[{"host":"*","headers":{"Bad Header":"1"}}]"#);
+        assert!(name.unwrap_err().to_string().contains("请求头名称无效"));
+        let value = parse_header_rules(r#"[{"host":"*","headers":{"Referer":"a\nb"}}]"#);
+        assert!(value.unwrap_err().to_string().contains("的值无效"));
+        assert!(parse_header_rules("not json").is_err());
+    }
+}
@@ -1,5 +1,11 @@
-use std::{fs::create_dir_all, path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::create_dir_all,
+    path::PathBuf,
+    sync::Arc,
+};
 
+use m3u8_rs::{AlternativeMedia, AlternativeMediaType, VariantStream};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
@@ -19,8 +25,16 @@ pub struct DownloadInfoContext {
     pub download_status: String,
     pub index_path: PathBuf,
     pub json_path: PathBuf,
+    // 旧版本的分片完成记录，恢复下载时导入分片清单
     pub json_success_path: PathBuf,
     pub ts_path: PathBuf,
+    pub variant: VariantSelector,
+    pub audio_renditions: RenditionSelector,
+    pub subtitle_renditions: RenditionSelector,
+    pub downloaded_bytes: u64,
+    // 选中媒体流的码率，单位比特每秒，用于估算总大小
+    #[serde(default)]
+    pub bandwidth: Option<u64>,
 }
 
 impl DownloadInfoContext {
@@ -53,6 +67,13 @@ impl DownloadInfoContext {
             json_path,
             json_success_path,
             ts_path,
+            variant: VariantSelector::parse(download_info.variant.as_deref()),
+            audio_renditions: RenditionSelector::parse(download_info.audio_renditions.as_deref()),
+            subtitle_renditions: RenditionSelector::parse(
+                download_info.subtitle_renditions.as_deref(),
+            ),
+            downloaded_bytes: download_info.downloaded_bytes.max(0) as u64,
+            bandwidth: None,
         })
     }
 }
@@ -63,14 +84,80 @@ pub struct DownloadInfoDetail {
     pub id: usize,
     pub url: Url,
     pub file_name: String,
-    pub data: Option<Vec<u8>>,
     pub success: bool,
+    // 分片使用的密钥地址，对应 DownloadSourceInfo.keys
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(default)]
+    pub iv: Option<String>,
+    // EXT-X-BYTERANGE 分片在资源中的起始偏移和长度
+    #[serde(default)]
+    pub byte_range: Option<(u64, u64)>,
+    // 所属的音轨或字幕，对应 DownloadSourceInfo.tracks，视频分片为空
+    #[serde(default)]
+    pub track: Option<usize>,
+    // 本轮下载的尝试次数，达到上限后标记为失败并记录原因
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub failed: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// 分片任务发回的下载结果，分片内容已经写入 file_name，size 为写入的字节数
+#[derive(Debug)]
+pub struct SegmentResult {
+    pub detail: DownloadInfoDetail,
+    pub size: u64,
+    pub checksum: Option<u32>,
+}
+
+/// 部分分片多次重试后仍下载失败，任务停在检查阶段，由用户选择强制合并或只重试失败的分片
+#[derive(Debug)]
+pub struct PartialDownload {
+    pub failed_count: usize,
+}
+
+impl std::fmt::Display for PartialDownload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} 个分片多次重试后仍下载失败", self.failed_count)
+    }
+}
+
+impl std::error::Error for PartialDownload {}
+
+/// 分片封装格式，带 EXT-X-MAP 初始化分片的为 fMP4
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaContainer {
+    #[default]
+    Ts,
+    Fmp4,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadSourceInfo {
     pub id: String,
-    pub m3u8_encrypt_key: M3u8EncryptKey,
+    #[serde(default)]
+    pub container: MediaContainer,
+    // 直播或 EVENT 播放列表的录制状态，点播为空
+    #[serde(default)]
+    pub live: Option<LiveRecordInfo>,
+    // 选中的 EXT-X-MEDIA 音轨和字幕
+    #[serde(default)]
+    pub tracks: Vec<MediaTrack>,
+    // 密钥地址 -> 密钥，播放列表中途轮换密钥时会有多个
+    #[serde(default)]
+    pub keys: HashMap<String, M3u8EncryptKey>,
+    // 媒体流码率和视频总时长，用于在下载初期估算总大小
+    #[serde(default)]
+    pub bandwidth: Option<u64>,
+    #[serde(default)]
+    pub duration: f64,
+    // 主媒体流中被过滤的广告分片在播放列表中的位置
+    #[serde(default)]
+    pub ad_segments: BTreeSet<usize>,
     pub download_info_list: Vec<DownloadInfoDetail>,
 }
 
@@ -78,25 +165,74 @@ impl DownloadSourceInfo {
     pub fn new() -> Self {
         Self {
             id: "".to_string(),
-            m3u8_encrypt_key: M3u8EncryptKey::default(),
+            container: MediaContainer::Ts,
+            live: None,
+            tracks: Vec::new(),
+            keys: HashMap::new(),
+            bandwidth: None,
+            duration: 0.0,
+            ad_segments: BTreeSet::new(),
             download_info_list: [].to_vec(),
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaTrackType {
+    Audio,
+    Subtitles,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaTrack {
+    pub id: usize,
+    pub ty: MediaTrackType,
+    pub name: String,
+    pub language: Option<String>,
+    pub media_url: Url,
+    #[serde(default)]
+    pub container: MediaContainer,
+    // 音轨、字幕的分片各自写入独立的索引文件
+    pub index_path: PathBuf,
+    pub ts_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveRecordInfo {
+    // 媒体播放列表地址，录制时定期重新拉取
+    pub media_url: Url,
+    // 开始录制时的媒体序列号，分片序号相对它计算
+    pub base_sequence: u64,
+    // 下一个待追加分片的媒体序列号
+    pub next_sequence: u64,
+    pub target_duration: u64,
+    // 开始录制的时间戳，单位秒
+    pub start_time: i64,
+    #[serde(default)]
+    pub current_map: Option<(String, Option<(u64, u64)>)>,
+}
+
 #[allow(non_snake_case)]
 #[derive(Debug, Clone)]
 pub struct DownloadInfoQueueDetail {
     pub id: usize,
     pub url: Url,
     pub file_name: String,
-    pub m3u8_encrypt_key: Arc<M3u8EncryptKey>,
+    pub key: Option<String>,
+    pub iv: Option<String>,
+    pub byte_range: Option<(u64, u64)>,
+    pub track: Option<usize>,
+    // 用于校验分片内容，字幕为空
+    pub container: Option<MediaContainer>,
+    pub m3u8_encrypt_key: Option<Arc<M3u8EncryptKey>>,
 }
 
 #[derive(PartialEq)]
 pub enum DownloadOperation {
     ParseSource,
     DownloadSlice,
+    RecordLive,
     CheckSource,
     Merger,
     DownloadEnd,
@@ -107,6 +243,7 @@ pub fn parse_operation_name(name: &str) -> DownloadOperation {
     match name {
         "parseSource" => DownloadOperation::ParseSource,
         "downloadSlice" => DownloadOperation::DownloadSlice,
+        "recordLive" => DownloadOperation::RecordLive,
         "checkSource" => DownloadOperation::CheckSource,
         "merger" => DownloadOperation::Merger,
         "downloadEnd" => DownloadOperation::DownloadEnd,
@@ -117,11 +254,405 @@ pub fn parse_operation_name(name: &str) -> DownloadOperation {
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct DownloadInfoResponse {
     pub id: String,
-    // parseSource 解析资源 downloadSlice 下载切片  checkSouce 检查资源完整性 merger 合并资源  downloadEnd 下载完成
+    // parseSource 解析资源 downloadSlice 下载切片 recordLive 录制直播 checkSouce 检查资源完整性 merger 合并资源  downloadEnd 下载完成
     pub status: String,
     pub download_count: Option<i32>,
     pub count: Option<usize>,
-    // wait 等待下载 downloading 下载中 downloadFail 下载失败 downloadSuccess 下载成功
+    // wait 等待下载 downloading 下载中 paused 已暂停 cancelled 已取消 downloadFail 下载失败 downloadPartial 部分分片失败 downloadSuccess 下载成功
     pub download_status: Option<String>,
-    pub mes_type: String,
+    // 下载失败的原因
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+// 下载消息格式的版本，字段有不兼容的修改时递增
+pub const DOWNLOAD_MESSAGE_VERSION: u32 = 1;
+
+/// 下载引擎发出的消息，mes_type 区分消息类型
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "mes_type", rename_all = "camelCase")]
+pub enum DownloadEvent {
+    // 任务开始下载或停留在当前阶段
+    StatusChange(DownloadInfoResponse),
+    ParseSourceEnd(DownloadInfoResponse),
+    DownloadSliceEnd(DownloadInfoResponse),
+    RecordLiveEnd(DownloadInfoResponse),
+    CheckSourceEnd(DownloadInfoResponse),
+    // 直播录制刷新播放列表后的分片总数
+    LiveRefresh(DownloadInfoResponse),
+    Progress(DownloadProgress),
+    // 任务结束，包括下载成功、失败、暂停和取消
+    End(DownloadInfoResponse),
+    // 客户端请求无法处理
+    Error { reason: String },
+}
+
+impl DownloadEvent {
+    pub fn response(&self) -> Option<&DownloadInfoResponse> {
+        match self {
+            DownloadEvent::StatusChange(r)
+            | DownloadEvent::ParseSourceEnd(r)
+            | DownloadEvent::DownloadSliceEnd(r)
+            | DownloadEvent::RecordLiveEnd(r)
+            | DownloadEvent::CheckSourceEnd(r)
+            | DownloadEvent::LiveRefresh(r)
+            | DownloadEvent::End(r) => Some(r),
+            DownloadEvent::Progress(_) | DownloadEvent::Error { .. } => None,
+        }
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct DownloadProgress {
+    pub id: String,
+    pub download_count: i32,
+    // 多次重试后仍失败的分片数
+    pub failed_count: usize,
+    pub concurrency: usize,
+    pub active: usize,
+    pub global_concurrency: usize,
+    // 限速，单位字节每秒，0 表示不限速
+    pub bandwidth_limit: u64,
+    pub global_bandwidth_limit: u64,
+    pub downloaded_bytes: u64,
+    // 估算的总字节数，无法估算时为空
+    pub total_bytes: Option<u64>,
+    // 当前和平均下载速度，单位字节每秒
+    pub speed: u64,
+    pub average_speed: u64,
+    // 预计剩余时间，单位秒
+    pub eta: Option<u64>,
+}
+
+/// 发送给前端和外部客户端的消息，带上版本号
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DownloadMessage {
+    pub version: u32,
+    #[serde(flatten)]
+    pub event: DownloadEvent,
+}
+
+impl DownloadMessage {
+    pub fn new(event: DownloadEvent) -> Self {
+        DownloadMessage {
+            version: DOWNLOAD_MESSAGE_VERSION,
+            event,
+        }
+    }
+}
+
+/// 主播放列表中媒体流的选择方式，以字符串形式保存在 download_info.variant 中
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum VariantSelector {
+    // 未指定时沿用第一个媒体流
+    First,
+    // highestBandwidth 最高码率
+    HighestBandwidth,
+    // lowestBandwidth 最低码率
+    LowestBandwidth,
+    // resolution:1920x1080 指定分辨率，没有完全匹配时取高度最接近的
+    Resolution(u64, u64),
+    // maxHeight:720 不超过指定高度的最高码率
+    MaxHeight(u64),
+    // maxBandwidth:3000000 不超过指定码率的最高码率，单位比特每秒
+    MaxBandwidth(u64),
+}
+
+impl VariantSelector {
+    pub fn parse(value: Option<&str>) -> Self {
+        let value = match value.map(str::trim) {
+            Some(v) if !v.is_empty() => v,
+            _ => return VariantSelector::First,
+        };
+        match value.split_once(':') {
+            Some(("resolution", r)) => match r.split_once('x') {
+                Some((w, h)) => match (w.trim().parse(), h.trim().parse()) {
+                    (Ok(w), Ok(h)) => VariantSelector::Resolution(w, h),
+                    _ => VariantSelector::First,
+                },
+                None => VariantSelector::First,
+            },
+            Some(("maxHeight", h)) => h
+                .trim()
+                .parse()
+                .map(VariantSelector::MaxHeight)
+                .unwrap_or(VariantSelector::First),
+            Some(("maxBandwidth", b)) => b
+                .trim()
+                .parse()
+                .map(VariantSelector::MaxBandwidth)
+                .unwrap_or(VariantSelector::First),
+            _ if value == "highestBandwidth" => VariantSelector::HighestBandwidth,
+            _ if value == "lowestBandwidth" => VariantSelector::LowestBandwidth,
+            _ => VariantSelector::First,
+        }
+    }
+
+    pub fn select<'a>(&self, variants: &'a [VariantStream]) -> Option<&'a VariantStream> {
+        let mut streams = variants.iter().filter(|v| !v.is_i_frame);
+        match self {
+            VariantSelector::First => streams.next(),
+            VariantSelector::HighestBandwidth => streams.max_by_key(|v| v.bandwidth),
+            VariantSelector::LowestBandwidth => streams.min_by_key(|v| v.bandwidth),
+            VariantSelector::Resolution(width, height) => {
+                let streams: Vec<&VariantStream> = streams.collect();
+                streams
+                    .iter()
+                    .filter(|v| {
+                        v.resolution
+                            .is_some_and(|r| r.width == *width && r.height == *height)
+                    })
+                    .max_by_key(|v| v.bandwidth)
+                    .or_else(|| {
+                        streams.iter().min_by_key(|v| {
+                            (
+                                v.resolution
+                                    .map_or(u64::MAX, |r| r.height.abs_diff(*height)),
+                                u64::MAX - v.bandwidth,
+                            )
+                        })
+                    })
+                    .copied()
+            }
+            VariantSelector::MaxHeight(max_height) => {
+                let streams: Vec<&VariantStream> = streams.collect();
+                streams
+                    .iter()
+                    .filter(|v| v.resolution.is_some_and(|r| r.height <= *max_height))
+                    .max_by_key(|v| v.bandwidth)
+                    // 全部超过限制时取分辨率最低的
+                    .or_else(|| {
+                        streams
+                            .iter()
+                            .min_by_key(|v| v.resolution.map_or(u64::MAX, |r| r.height))
+                    })
+                    .copied()
+            }
+            VariantSelector::MaxBandwidth(max_bandwidth) => {
+                let streams: Vec<&VariantStream> = streams.collect();
+                streams
+                    .iter()
+                    .filter(|v| v.bandwidth <= *max_bandwidth)
+                    .max_by_key(|v| v.bandwidth)
+                    // 全部超过限制时取码率最低的
+                    .or_else(|| streams.iter().min_by_key(|v| v.bandwidth))
+                    .copied()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct M3u8Variant {
+    pub url: String,
+    pub bandwidth: u64,
+    pub average_bandwidth: Option<u64>,
+    // 1920x1080
+    pub resolution: Option<String>,
+    pub codecs: Option<String>,
+    pub frame_rate: Option<f64>,
+    // 保存到 download_info.variant 的选择值
+    pub variant: Option<String>,
+    // 引用的 EXT-X-MEDIA 分组
+    pub audio: Option<String>,
+    pub subtitles: Option<String>,
+}
+
+/// EXT-X-MEDIA 音轨、字幕的选择方式，保存在 download_info.audio_renditions / subtitle_renditions 中
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RenditionSelector {
+    // 未指定时不下载独立的音轨和字幕
+    None,
+    // default 媒体流默认的音轨或字幕
+    Default,
+    // all 全部
+    All,
+    // 按 NAME 或 LANGUAGE 匹配，逗号分隔
+    Names(Vec<String>),
+}
+
+impl RenditionSelector {
+    pub fn parse(value: Option<&str>) -> Self {
+        let names: Vec<String> = value
+            .unwrap_or_default()
+            .split(',')
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect();
+        if names.is_empty() {
+            RenditionSelector::None
+        } else if names.iter().any(|v| v == "all") {
+            RenditionSelector::All
+        } else if names.iter().any(|v| v == "default") {
+            RenditionSelector::Default
+        } else {
+            RenditionSelector::Names(names)
+        }
+    }
+
+    /// 从媒体流引用的分组中选出需要下载的音轨或字幕，没有 URI 的已经包含在媒体流中
+    pub fn select<'a>(
+        &self,
+        alternatives: &'a [AlternativeMedia],
+        media_type: AlternativeMediaType,
+        group_id: Option<&str>,
+    ) -> Vec<&'a AlternativeMedia> {
+        let group_id = match group_id {
+            Some(g) if *self != RenditionSelector::None => g,
+            _ => return Vec::new(),
+        };
+        alternatives
+            .iter()
+            .filter(|m| m.media_type == media_type && m.group_id == group_id && m.uri.is_some())
+            .filter(|m| match self {
+                RenditionSelector::None => false,
+                RenditionSelector::Default => m.default,
+                RenditionSelector::All => true,
+                RenditionSelector::Names(names) => names
+                    .iter()
+                    .any(|n| *n == m.name || m.language.as_deref() == Some(n.as_str())),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct M3u8Rendition {
+    // audio 音轨 subtitles 字幕
+    pub media_type: MediaTrackType,
+    pub group_id: String,
+    pub name: String,
+    pub language: Option<String>,
+    pub default: bool,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct M3u8StreamList {
+    pub variants: Vec<M3u8Variant>,
+    pub renditions: Vec<M3u8Rendition>,
+}
+
+#[cfg(test)]
+mod tests {
+    use m3u8_rs::Resolution;
+
+    use super::*;
+
+    fn stream(uri: &str, bandwidth: u64, resolution: Option<(u64, u64)>) -> VariantStream {
+        VariantStream {
+            uri: uri.to_string(),
+            bandwidth,
+            resolution: resolution.map(|(width, height)| Resolution { width, height }),
+            ..Default::default()
+        }
+    }
+
+    // 第一个媒体流不是最高或最低码率，I 帧流码率最高
+    fn variants() -> Vec<VariantStream> {
+        vec![
+            stream("720.m3u8", 2_500_000, Some((1280, 720))),
+            stream("1080.m3u8", 5_000_000, Some((1920, 1080))),
+            stream("360.m3u8", 800_000, Some((640, 360))),
+            stream("1080_low.m3u8", 4_000_000, Some((1920, 1080))),
+            VariantStream {
+                is_i_frame: true,
+                ..stream("iframe.m3u8", 9_000_000, Some((1920, 1080)))
+            },
+        ]
+    }
+
+    fn select(value: &str) -> Option<String> {
+        VariantSelector::parse(Some(value))
+            .select(&variants())
+            .map(|v| v.uri.clone())
+    }
+
+    #[test]
+    fn parse_selectors() {
+        assert_eq!(VariantSelector::parse(None), VariantSelector::First);
+        assert_eq!(VariantSelector::parse(Some(" ")), VariantSelector::First);
+        assert_eq!(
+            VariantSelector::parse(Some("highestBandwidth")),
+            VariantSelector::HighestBandwidth
+        );
+        assert_eq!(
+            VariantSelector::parse(Some("lowestBandwidth")),
+            VariantSelector::LowestBandwidth
+        );
+        assert_eq!(
+            VariantSelector::parse(Some("resolution:1920x1080")),
+            VariantSelector::Resolution(1920, 1080)
+        );
+        assert_eq!(
+            VariantSelector::parse(Some("maxHeight:720")),
+            VariantSelector::MaxHeight(720)
+        );
+        assert_eq!(
+            VariantSelector::parse(Some("maxBandwidth:3000000")),
+            VariantSelector::MaxBandwidth(3_000_000)
+        );
+        // 无法识别的值沿用第一个媒体流
+        assert_eq!(
+            VariantSelector::parse(Some("resolution:1080p")),
+            VariantSelector::First
+        );
+        assert_eq!(
+            VariantSelector::parse(Some("maxHeight:high")),
+            VariantSelector::First
+        );
+        assert_eq!(VariantSelector::parse(Some("best")), VariantSelector::First);
+    }
+
+    #[test]
+    fn select_first_highest_and_lowest() {
+        assert_eq!(select("").as_deref(), Some("720.m3u8"));
+        // I 帧流不参与选择
+        assert_eq!(select("highestBandwidth").as_deref(), Some("1080.m3u8"));
+        assert_eq!(select("lowestBandwidth").as_deref(), Some("360.m3u8"));
+    }
+
+    #[test]
+    fn select_resolution() {
+        // 同一分辨率取码率最高的
+        assert_eq!(select("resolution:1920x1080").as_deref(), Some("1080.m3u8"));
+        assert_eq!(select("resolution:640x360").as_deref(), Some("360.m3u8"));
+        // 没有完全匹配时取高度最接近的
+        assert_eq!(select("resolution:1024x576").as_deref(), Some("720.m3u8"));
+        assert_eq!(select("resolution:3840x2160").as_deref(), Some("1080.m3u8"));
+    }
+
+    #[test]
+    fn select_max_height_and_bandwidth() {
+        assert_eq!(select("maxHeight:720").as_deref(), Some("720.m3u8"));
+        assert_eq!(select("maxHeight:1080").as_deref(), Some("1080.m3u8"));
+        assert_eq!(select("maxBandwidth:3000000").as_deref(), Some("720.m3u8"));
+        assert_eq!(
+            select("maxBandwidth:4000000").as_deref(),
+            Some("1080_low.m3u8")
+        );
+    }
+
+    #[test]
+    fn select_falls_back_when_nothing_matches() {
+        // 全部超过限制时取最低的
+        assert_eq!(select("maxHeight:240").as_deref(), Some("360.m3u8"));
+        assert_eq!(select("maxBandwidth:100000").as_deref(), Some("360.m3u8"));
+        // 没有分辨率信息时按码率选择
+        let variants = vec![
+            stream("a.m3u8", 1_000_000, None),
+            stream("b.m3u8", 2_000_000, None),
+        ];
+        let selected = VariantSelector::Resolution(1280, 720).select(&variants);
+        assert_eq!(selected.map(|v| v.uri.as_str()), Some("b.m3u8"));
+        // 只有 I 帧流时没有可选的媒体流
+        let variants = vec![VariantStream {
+            is_i_frame: true,
+            ..stream("iframe.m3u8", 1_000_000, None)
+        }];
+        assert!(VariantSelector::HighestBandwidth
+            .select(&variants)
+            .is_none());
+        assert!(VariantSelector::First.select(&[]).is_none());
+    }
 }
@@ -1,11 +1,37 @@
-use tauri::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
-use tauri_plugin_http::reqwest;
-use url::Url;
-use std::{
-    time::Duration,
+use futures::TryStreamExt;
+use rand::Rng;
+use std::time::Duration;
+use tauri::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use tokio::{
+    fs::{remove_file, rename, File},
+    io::AsyncWriteExt,
 };
+use url::Url;
 
-use crate::download::m3u8_encrypt_key::{M3u8EncryptKey, KeyType};
+use crate::conf::get_string;
+use crate::download::bandwidth::{throttle, TokenBucket};
+use crate::download::http_client;
+use crate::download::m3u8_encrypt_key::{M3u8EncryptKey, SegmentDecryptor};
+use crate::download::site_headers::site_headers;
+use crate::download::types::MediaContainer;
+
+// TS 包长度和同步字节，校验开头的几个包
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+const TS_CHECK_PACKETS: usize = 5;
+// 校验分片格式时读取的开头字节数
+const SEGMENT_HEAD_SIZE: usize = TS_PACKET_SIZE * TS_CHECK_PACKETS;
+// fMP4 初始化分片和媒体分片开头的 box 类型
+const FMP4_BOX_TYPES: [&[u8; 4]; 8] = [
+    b"ftyp", b"styp", b"moov", b"moof", b"sidx", b"emsg", b"prft", b"free",
+];
+// CDN 返回错误页面时的响应类型
+const ERROR_CONTENT_TYPES: [&str; 4] = [
+    "text/html",
+    "application/json",
+    "application/xml",
+    "text/xml",
+];
 
 pub async fn download_request(url: &Url) -> anyhow::Result<Vec<u8>> {
     let mut headers = HeaderMap::new();
@@ -20,40 +46,191 @@ pub async fn download_request(url: &Url) -> anyhow::Result<Vec<u8>> {
         HeaderValue::from_str(base_url.as_str()).unwrap(),
     );
 
-    let client = reqwest::Client::new();
+    // 站点配置的请求头覆盖默认的 referer
+    headers.extend(site_headers(url));
+    let client = http_client::client()?;
     let resp = client.get(url.as_str()).headers(headers).send().await?;
     if resp.status() != StatusCode::OK {
-        panic!(
-            "{}",
-            format!(
-                "{} download failed. http code: {}",
-                url.as_str(),
-                resp.status()
-            )
-        )
+        return Err(anyhow::anyhow!(
+            "{} download failed. http code: {}",
+            url.as_str(),
+            resp.status()
+        ));
     }
     Ok(resp.bytes().await?.to_vec())
 }
 
-pub async fn download_ts(url: &str, m3u8_encrypt_key: &M3u8EncryptKey) -> anyhow::Result<(bool, Vec<u8>)> {
-    let mut data = Vec::new();
-    let mut success = false;
-    // 创建带10秒超时的HTTP客户端
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(10))
-        .build()?;
-    let rp = client.get(url).send().await?;
-    if rp.status() == StatusCode::OK {
-        let d = rp.bytes().await?;
-        data = d.to_vec();
-        if !data.is_empty() && !matches!(m3u8_encrypt_key.ty, KeyType::None) {
-            if let Some(data1) = m3u8_encrypt_key.decode(&mut data)? {
-                success = true;
-                data = data1;
+/// 下载分片，边下载边解密写入临时文件，校验通过后重命名为 file_name，返回写入的字节数和 CRC32
+pub async fn download_segment(
+    url: &str,
+    m3u8_encrypt_key: Option<&M3u8EncryptKey>,
+    iv: Option<&str>,
+    byte_range: Option<(u64, u64)>,
+    container: Option<MediaContainer>,
+    task_bandwidth: Option<&TokenBucket>,
+    file_name: &str,
+) -> anyhow::Result<(u64, u32)> {
+    // 限速时分片可能需要较长时间，只限制读取间隔，不限制总时长
+    let client = http_client::stream_client()?;
+    let mut request = client.get(url);
+    if let Ok(segment_url) = Url::parse(url) {
+        request = request.headers(site_headers(&segment_url));
+    }
+    if let Some((offset, length)) = byte_range {
+        request = request.header(
+            header::RANGE,
+            format!("bytes={}-{}", offset, (offset + length).saturating_sub(1)),
+        );
+    }
+    let rp = request.send().await?;
+    let status = rp.status();
+    if status != StatusCode::OK && status != StatusCode::PARTIAL_CONTENT {
+        return Err(anyhow::anyhow!("http code: {}", status));
+    }
+    let content_type = rp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_ascii_lowercase())
+        .unwrap_or_default();
+    if ERROR_CONTENT_TYPES
+        .iter()
+        .any(|t| content_type.starts_with(t))
+    {
+        return Err(anyhow::anyhow!("分片响应类型错误: {}", content_type));
+    }
+    // 服务器忽略 Range 返回完整资源时自行截取
+    let window = match byte_range {
+        Some((offset, length)) if status == StatusCode::OK => Some((offset, offset + length)),
+        _ => None,
+    };
+    let mut decryptor = match m3u8_encrypt_key {
+        Some(m3u8_encrypt_key) => m3u8_encrypt_key.decryptor(iv)?,
+        None => SegmentDecryptor::Plain,
+    };
+
+    let part_path = format!("{}.part", file_name);
+    let result = async {
+        let mut file = File::create(&part_path).await?;
+        let content_length = rp.content_length();
+        let mut received: u64 = 0;
+        let mut written: u64 = 0;
+        let mut hasher = crc32fast::Hasher::new();
+        // 分片开头的明文，用于校验分片格式
+        let mut head = Vec::with_capacity(SEGMENT_HEAD_SIZE);
+        // 按块读取响应，每块都经过令牌桶限速，解密后直接写入文件
+        let mut stream = rp.bytes_stream();
+        while let Some(chunk) = stream.try_next().await? {
+            throttle(task_bandwidth, chunk.len()).await;
+            let position = received;
+            received += chunk.len() as u64;
+            let chunk = match window {
+                Some((start, end)) => {
+                    let len = chunk.len() as u64;
+                    let from = start.saturating_sub(position).min(len) as usize;
+                    let to = end.saturating_sub(position).min(len) as usize;
+                    chunk.slice(from..to.max(from))
+                }
+                None => chunk,
+            };
+            let plain = decryptor.update(&chunk)?;
+            hasher.update(&plain);
+            write_plain(&mut file, &mut head, &plain, &mut written).await?;
+            // 截取范围已读完，不再下载剩余的资源
+            if matches!(window, Some((_, end)) if received >= end) {
+                break;
+            }
+        }
+        drop(stream);
+        match (window, content_length) {
+            // 截取时响应长度是完整资源的长度，只校验是否读到了范围末尾
+            (Some((_, end)), _) if received < end => {
+                return Err(anyhow::anyhow!("分片长度不完整: {}/{}", received, end));
+            }
+            (None, Some(length)) if length != received => {
+                return Err(anyhow::anyhow!("分片长度不完整: {}/{}", received, length));
             }
-        } else {
-            success = true;
+            _ => {}
         }
+        let plain = decryptor.finish()?;
+        hasher.update(&plain);
+        write_plain(&mut file, &mut head, &plain, &mut written).await?;
+        file.flush().await?;
+        // 部分 CDN 把正常分片伪装成图片类型返回，图片类型只在内容校验失败时提示
+        validate_segment(&head, container).map_err(|e| {
+            if content_type.starts_with("image/") {
+                anyhow::anyhow!("{}，响应类型为 {}", e, content_type)
+            } else {
+                e
+            }
+        })?;
+        rename(&part_path, file_name).await?;
+        Ok((written, hasher.finalize()))
+    }
+    .await;
+    if result.is_err() {
+        let _ = remove_file(&part_path).await;
     }
-    Ok((success, data))
+    result
+}
+
+async fn write_plain(
+    file: &mut File,
+    head: &mut Vec<u8>,
+    plain: &[u8],
+    written: &mut u64,
+) -> anyhow::Result<()> {
+    let take = SEGMENT_HEAD_SIZE
+        .saturating_sub(head.len())
+        .min(plain.len());
+    head.extend_from_slice(&plain[..take]);
+    file.write_all(plain).await?;
+    *written += plain.len() as u64;
+    Ok(())
+}
+
+/// 校验解密后的分片内容，container 为空时（字幕）不校验内容
+pub fn validate_segment(data: &[u8], container: Option<MediaContainer>) -> anyhow::Result<()> {
+    match container {
+        Some(MediaContainer::Ts) => {
+            if data.len() < TS_PACKET_SIZE {
+                return Err(anyhow::anyhow!("TS 分片过小: {} 字节", data.len()));
+            }
+            let synced = data
+                .iter()
+                .step_by(TS_PACKET_SIZE)
+                .take(TS_CHECK_PACKETS)
+                .all(|b| *b == TS_SYNC_BYTE);
+            // 纯音频的 AAC、MP3 分片以 ID3 标签或 ADTS 同步字开头
+            let packed_audio =
+                data.starts_with(b"ID3") || (data[0] == 0xFF && data[1] & 0xF0 == 0xF0);
+            if !synced && !packed_audio {
+                return Err(anyhow::anyhow!("TS 分片同步字节错误"));
+            }
+        }
+        Some(MediaContainer::Fmp4) => {
+            let box_type = data.get(4..8).unwrap_or_default();
+            if !FMP4_BOX_TYPES.iter().any(|t| t.as_slice() == box_type) {
+                return Err(anyhow::anyhow!("fMP4 分片格式错误"));
+            }
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+// segmentRetryCount 单个分片的最大尝试次数
+pub fn segment_max_attempts() -> u32 {
+    get_string("segmentRetryCount")
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .unwrap_or(5)
+}
+
+/// 第 attempt 次失败后的等待时间，指数退避到 30 秒封顶，并加上随机抖动避免同时重试
+pub fn retry_backoff(attempt: u32) -> Duration {
+    let base = (500u64 << attempt.saturating_sub(1).min(6)).min(30_000);
+    let jitter = rand::rng().random_range(0..=base / 2);
+    Duration::from_millis(base + jitter)
 }
@@ -15,7 +15,7 @@ mod schema;
 mod utils;
 
 use crate::app::hotkey;
-use download::file_download;
+use download::{ad_filter, file_download, scheduler, site_headers};
 use tauri_plugin_log::{Target, TargetKind};
 use url::Url;
 
@@ -124,6 +124,8 @@ pub fn run() {
                 let app_handle_clone = app_handle_for_async.clone();
                 app_handle_clone
                     .run_on_main_thread(move || {
+                        // 后台调度下载队列中的任务
+                        tauri::async_runtime::spawn(scheduler::run());
                         tauri::async_runtime::spawn(async move {
                             file_download::init().await;
                         });
@@ -149,6 +151,15 @@ pub fn run() {
             hotkey::cmd::unregister_shortcut_by_frontend,
             file_download::cmd::retry_download,
             file_download::cmd::movie_merger,
+            file_download::cmd::retry_failed_segments,
+            file_download::cmd::pause_download,
+            file_download::cmd::resume_download,
+            file_download::cmd::cancel_download,
+            file_download::cmd::stop_live_record,
+            file_download::cmd::get_m3u8_variants,
+            file_download::cmd::get_download_server_token,
+            ad_filter::cmd::filter_m3u8_ads,
+            site_headers::cmd::get_site_headers,
             app::cmds::open_devtools,
             app::cmds::download_file_task,
             app::cmds::download_miniserve_task,
@@ -168,6 +179,9 @@ pub fn run() {
             orm::download_info::cmds::get_download_info_by_id,
             orm::download_info::cmds::save_download_info,
             orm::download_info::cmds::delete_download_info,
+            orm::download_queue::cmds::select_download_queue,
+            orm::download_queue::cmds::update_download_queue_priority,
+            orm::download_queue::cmds::reorder_download_queue,
             orm::star::cmds::star_movie,
             orm::star::cmds::delete_star,
             orm::star::cmds::select_all_star,
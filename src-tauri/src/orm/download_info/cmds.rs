@@ -1,9 +1,11 @@
 use crate::download::file_download::service::retry_download;
-use crate::orm::download_info::service::get_download_save_folder;
+use crate::download::scheduler;
+use crate::orm::download_info::service::get_download_task_folder;
 use crate::orm::download_info::service::get_download_save_path;
 use crate::orm::download_info::types::DownloadInfo;
 
 use crate::orm::download_info::types::DownloadInfoSave;
+use crate::orm::download_segment::service::delete_segments;
 use crate::orm::get_database_pool;
 use crate::schema::download_info::dsl as download_info_dsl;
 use crate::utils;
@@ -11,6 +13,7 @@ use diesel::ExpressionMethods;
 use diesel::OptionalExtension;
 use diesel::QueryDsl;
 use diesel::RunQueryDsl;
+use log::warn;
 
 #[tauri::command]
 pub fn select_all_download_info() -> Result<Vec<DownloadInfo>, String> {
@@ -66,6 +69,14 @@ pub fn save_download_info(download_infos: Vec<DownloadInfoSave>) -> Result<(), S
             download_status: download_info.download_status,
             create_time: now.clone(),
             update_time: Some(now.clone()),
+            variant: download_info.variant,
+            audio_renditions: download_info.audio_renditions,
+            subtitle_renditions: download_info.subtitle_renditions,
+            downloaded_bytes: 0,
+            total_bytes: None,
+            speed: 0,
+            average_speed: 0,
+            eta: None,
         })
         .collect::<Vec<DownloadInfo>>();
     diesel::insert_into(download_info_dsl::download_info)
@@ -90,9 +101,17 @@ pub fn delete_download_info(id: &str) -> Result<Option<DownloadInfo>, String> {
         .map_err(|e| format!("查询下载信息失败: {}", e))?;
 
     if let Some(info) = download_info.clone() {
-        if let Some(folder) = get_download_save_folder(&info) {
-            utils::del_movie_path(folder);
+        // 等待中的任务同时移出下载队列
+        scheduler::dequeue(id).map_err(|e| format!("移出下载队列失败: {}", e))?;
+        // 与取消下载相同，目录根据记录校验，无效时只删除记录不删除文件
+        match get_download_task_folder(&info) {
+            Some(folder) => utils::del_movie_path(folder.to_string_lossy().to_string()),
+            None => warn!(
+                "下载目录无效，跳过删除文件: {}/{}",
+                info.movie_name, info.sub_title_name
+            ),
         }
+        delete_segments(id).map_err(|e| format!("删除分片清单失败: {}", e))?;
         // 删除数据
         diesel::delete(download_info_dsl::download_info)
             .filter(download_info_dsl::id.eq(id))
@@ -5,7 +5,8 @@ use crate::{
     conf::get_string,
     orm::download_info::types::{DownloadInfo, DownloadInfoUpdate},
 };
-use diesel::{ExpressionMethods, RunQueryDsl};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use std::path::{Component, Path, PathBuf};
 
 pub fn update_download_by_id(download_info_update: DownloadInfoUpdate) -> anyhow::Result<usize> {
     let mut db = get_database_pool()?;
@@ -25,12 +26,45 @@ pub fn update_download_by_id(download_info_update: DownloadInfoUpdate) -> anyhow
             download_info_update
                 .download_status
                 .map(|download_status| download_info_dsl::download_status.eq(download_status)),
+            download_info_update
+                .downloaded_bytes
+                .map(|downloaded_bytes| download_info_dsl::downloaded_bytes.eq(downloaded_bytes)),
+            download_info_update
+                .total_bytes
+                .map(|total_bytes| download_info_dsl::total_bytes.eq(total_bytes)),
+            download_info_update
+                .speed
+                .map(|speed| download_info_dsl::speed.eq(speed)),
+            download_info_update
+                .average_speed
+                .map(|average_speed| download_info_dsl::average_speed.eq(average_speed)),
+            download_info_update
+                .eta
+                .map(|eta| download_info_dsl::eta.eq(eta)),
+            download_info_update
+                .variant
+                .map(|variant| download_info_dsl::variant.eq(variant)),
+            download_info_update
+                .audio_renditions
+                .map(|audio_renditions| download_info_dsl::audio_renditions.eq(audio_renditions)),
+            download_info_update.subtitle_renditions.map(|subtitle_renditions| {
+                download_info_dsl::subtitle_renditions.eq(subtitle_renditions)
+            }),
             download_info_dsl::update_time.eq(&now),
         ))
         .execute(&mut db)?;
     Ok(rows_affected)
 }
 
+pub fn find_download_by_id(id: &str) -> anyhow::Result<Option<DownloadInfo>> {
+    let mut db = get_database_pool()?;
+    let download_info = download_info_dsl::download_info
+        .filter(download_info_dsl::id.eq(id))
+        .first::<DownloadInfo>(&mut db)
+        .optional()?;
+    Ok(download_info)
+}
+
 pub fn get_download_save_path(download_info: &DownloadInfo) -> Option<String> {
     let sub_title_name = &download_info.sub_title_name;
     let download_save_path = get_string("downloadSavePath");
@@ -39,20 +73,42 @@ pub fn get_download_save_path(download_info: &DownloadInfo) -> Option<String> {
             "{}\\{}\\{}\\{}.mp4",
             download_save_path, download_info.movie_name, sub_title_name, sub_title_name
         );
-        return Some(url);
+        if utils::exists(Path::new(&url)) {
+            return Some(url);
+        }
+        // 未使用 ffmpeg 合并的 TS 分片保存为 .ts，直接下载的视频保留原来的格式
+        let other_url = ["ts", "flv", "m4v", "mkv", "webm", "mov"]
+            .iter()
+            .map(|ext| format!("{}.{}", url.trim_end_matches(".mp4"), ext))
+            .find(|other_url| utils::exists(Path::new(other_url)));
+        return Some(other_url.unwrap_or(url));
     }
     None
 }
 
-pub fn get_download_save_folder(download_info: &DownloadInfo) -> Option<String> {
-    let sub_title_name = &download_info.sub_title_name;
+/// 下载任务的目录，只根据数据库中的记录计算，删除文件和校验外部请求时使用
+///
+/// 影片名或剧集名为空或包含路径分隔符时会指向上级目录甚至下载根目录，这种情况返回 None
+pub fn get_download_task_folder(download_info: &DownloadInfo) -> Option<PathBuf> {
     let download_save_path = get_string("downloadSavePath");
-    if !download_save_path.is_empty() {
-        let url = format!(
-            "{}\\{}\\{}",
-            download_save_path, download_info.movie_name, sub_title_name
-        );
-        return Some(url);
+    if download_save_path.is_empty() {
+        return None;
     }
-    None
+    let single_component = |name: &str| {
+        let mut components = Path::new(name).components();
+        matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        )
+    };
+    if !single_component(&download_info.movie_name)
+        || !single_component(&download_info.sub_title_name)
+    {
+        return None;
+    }
+    let root = PathBuf::from(download_save_path);
+    let folder = root
+        .join(&download_info.movie_name)
+        .join(&download_info.sub_title_name);
+    (folder != root).then_some(folder)
 }
@@ -17,6 +17,19 @@ pub struct DownloadInfo {
     pub download_status: String,
     pub create_time: String,
     pub update_time: Option<String>,
+    pub variant: Option<String>,
+    pub audio_renditions: Option<String>,
+    pub subtitle_renditions: Option<String>,
+    // 旧版本前端传回的对象没有进度字段
+    #[serde(default)]
+    pub downloaded_bytes: i64,
+    // 估算的总字节数，速度单位字节每秒，剩余时间单位秒
+    pub total_bytes: Option<i64>,
+    #[serde(default)]
+    pub speed: i64,
+    #[serde(default)]
+    pub average_speed: i64,
+    pub eta: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -28,6 +41,9 @@ pub struct DownloadInfoSave {
     pub download_count: i32,
     pub count: i32,
     pub download_status: String,
+    pub variant: Option<String>,
+    pub audio_renditions: Option<String>,
+    pub subtitle_renditions: Option<String>,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
@@ -37,4 +53,12 @@ pub struct DownloadInfoUpdate {
     pub download_count: Option<i32>,
     pub count: Option<i32>,
     pub download_status: Option<String>,
+    pub downloaded_bytes: Option<i64>,
+    pub total_bytes: Option<i64>,
+    pub speed: Option<i64>,
+    pub average_speed: Option<i64>,
+    pub eta: Option<i64>,
+    pub variant: Option<String>,
+    pub audio_renditions: Option<String>,
+    pub subtitle_renditions: Option<String>,
 }
@@ -0,0 +1,20 @@
+use crate::orm::download_queue::service::{
+    get_all_download_queue, reorder_download, update_download_priority,
+};
+use crate::orm::download_queue::types::DownloadQueue;
+
+#[tauri::command]
+pub fn select_download_queue() -> Result<Vec<DownloadQueue>, String> {
+    get_all_download_queue().map_err(|e| format!("查询下载队列失败: {}", e))
+}
+
+#[tauri::command]
+pub fn update_download_queue_priority(download_id: &str, priority: i32) -> Result<usize, String> {
+    update_download_priority(download_id, priority)
+        .map_err(|e| format!("修改下载优先级失败: {}", e))
+}
+
+#[tauri::command]
+pub fn reorder_download_queue(download_ids: Vec<String>) -> Result<(), String> {
+    reorder_download(&download_ids).map_err(|e| format!("调整下载顺序失败: {}", e))
+}
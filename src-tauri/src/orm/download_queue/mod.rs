@@ -0,0 +1,3 @@
+pub mod cmds;
+pub mod service;
+pub mod types;
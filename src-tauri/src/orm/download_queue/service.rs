@@ -0,0 +1,134 @@
+use diesel::dsl::max;
+use diesel::ExpressionMethods;
+use diesel::OptionalExtension;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+
+use crate::utils;
+use crate::{
+    orm::{download_queue::types::DownloadQueue, get_database_pool},
+    schema::download_queue::dsl as download_queue_dsl,
+};
+
+pub fn get_all_download_queue() -> anyhow::Result<Vec<DownloadQueue>> {
+    let mut db = get_database_pool()?;
+    let download_queue = download_queue_dsl::download_queue
+        .order_by((
+            download_queue_dsl::priority.desc(),
+            download_queue_dsl::position.asc(),
+        ))
+        .load::<DownloadQueue>(&mut db)?;
+    Ok(download_queue)
+}
+
+/// 加入下载队列，已在队列中的任务保持原来的位置
+pub fn enqueue_download(download_id: &str, priority: i32) -> anyhow::Result<()> {
+    let mut db = get_database_pool()?;
+    let exists = download_queue_dsl::download_queue
+        .filter(download_queue_dsl::download_id.eq(download_id))
+        .first::<DownloadQueue>(&mut db)
+        .optional()?;
+    if exists.is_some() {
+        return Ok(());
+    }
+    let position_max = download_queue_dsl::download_queue
+        .select(max(download_queue_dsl::position))
+        .first::<Option<f64>>(&mut db)?
+        .unwrap_or(0.00);
+    let now = utils::get_current_time_str();
+    diesel::insert_into(download_queue_dsl::download_queue)
+        .values(&DownloadQueue {
+            id: utils::uuid(),
+            download_id: download_id.to_string(),
+            priority,
+            position: position_max + 10.0,
+            status: "wait".to_string(),
+            attempt_count: 0,
+            enqueue_time: now.clone(),
+            update_time: Some(now),
+        })
+        .execute(&mut db)?;
+    Ok(())
+}
+
+/// 取出优先级最高、排在最前的等待任务并标记为下载中，启动次数加一
+pub fn take_next_download() -> anyhow::Result<Option<DownloadQueue>> {
+    let mut db = get_database_pool()?;
+    let next = download_queue_dsl::download_queue
+        .filter(download_queue_dsl::status.eq("wait"))
+        .order_by((
+            download_queue_dsl::priority.desc(),
+            download_queue_dsl::position.asc(),
+        ))
+        .first::<DownloadQueue>(&mut db)
+        .optional()?;
+    if let Some(mut next) = next {
+        next.status = "running".to_string();
+        next.attempt_count += 1;
+        diesel::update(download_queue_dsl::download_queue)
+            .filter(download_queue_dsl::id.eq(&next.id))
+            .set((
+                download_queue_dsl::status.eq(&next.status),
+                download_queue_dsl::attempt_count.eq(next.attempt_count),
+                download_queue_dsl::update_time.eq(utils::get_current_time_str()),
+            ))
+            .execute(&mut db)?;
+        return Ok(Some(next));
+    }
+    Ok(None)
+}
+
+pub fn remove_download(download_id: &str) -> anyhow::Result<usize> {
+    let mut db = get_database_pool()?;
+    let rows_affected = diesel::delete(download_queue_dsl::download_queue)
+        .filter(download_queue_dsl::download_id.eq(download_id))
+        .execute(&mut db)?;
+    Ok(rows_affected)
+}
+
+/// 应用上次退出时仍在下载的任务重新排队
+pub fn reset_running_download() -> anyhow::Result<usize> {
+    let mut db = get_database_pool()?;
+    let rows_affected = diesel::update(download_queue_dsl::download_queue)
+        .filter(download_queue_dsl::status.eq("running"))
+        .set(download_queue_dsl::status.eq("wait"))
+        .execute(&mut db)?;
+    Ok(rows_affected)
+}
+
+pub fn update_download_priority(download_id: &str, priority: i32) -> anyhow::Result<usize> {
+    let mut db = get_database_pool()?;
+    let rows_affected = diesel::update(download_queue_dsl::download_queue)
+        .filter(download_queue_dsl::download_id.eq(download_id))
+        .set((
+            download_queue_dsl::priority.eq(priority),
+            download_queue_dsl::update_time.eq(utils::get_current_time_str()),
+        ))
+        .execute(&mut db)?;
+    Ok(rows_affected)
+}
+
+/// 按传入的顺序重新设置排序，未传入的任务排在后面
+pub fn reorder_download(download_ids: &[String]) -> anyhow::Result<()> {
+    let mut db = get_database_pool()?;
+    let now = utils::get_current_time_str();
+    let position_max = download_queue_dsl::download_queue
+        .select(max(download_queue_dsl::position))
+        .first::<Option<f64>>(&mut db)?
+        .unwrap_or(0.00);
+    // 先把所有任务移到后面，再按顺序放到前面
+    let offset = position_max + download_ids.len() as f64 * 10.0;
+    diesel::update(download_queue_dsl::download_queue)
+        .set(download_queue_dsl::position.eq(download_queue_dsl::position + offset))
+        .execute(&mut db)?;
+    for (index, download_id) in download_ids.iter().enumerate() {
+        diesel::update(download_queue_dsl::download_queue)
+            .filter(download_queue_dsl::download_id.eq(download_id))
+            .set((
+                download_queue_dsl::position.eq((index as f64 + 1.0) * 10.0),
+                download_queue_dsl::update_time.eq(&now),
+            ))
+            .execute(&mut db)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Queryable, Selectable, QueryableByName, Insertable,
+)]
+#[diesel(table_name = crate::schema::download_queue)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct DownloadQueue {
+    pub id: String,
+    pub download_id: String,
+    pub priority: i32,
+    pub position: f64,
+    // wait 等待 running 下载中
+    pub status: String,
+    pub attempt_count: i32,
+    pub enqueue_time: String,
+    pub update_time: Option<String>,
+}
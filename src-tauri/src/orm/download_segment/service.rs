@@ -0,0 +1,52 @@
+use diesel::Connection;
+use diesel::ExpressionMethods;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+
+use crate::{
+    orm::{download_segment::types::DownloadSegment, get_database_pool},
+    schema::download_segment::dsl as download_segment_dsl,
+};
+
+pub fn get_segments(download_id: &str) -> anyhow::Result<Vec<DownloadSegment>> {
+    let mut db = get_database_pool()?;
+    let segments = download_segment_dsl::download_segment
+        .filter(download_segment_dsl::download_id.eq(download_id))
+        .load::<DownloadSegment>(&mut db)?;
+    Ok(segments)
+}
+
+/// 批量写入分片记录，同一个分片的记录覆盖之前的状态
+pub fn save_segments(segments: &[DownloadSegment]) -> anyhow::Result<()> {
+    if segments.is_empty() {
+        return Ok(());
+    }
+    let mut db = get_database_pool()?;
+    db.transaction(|db| {
+        for segment in segments {
+            diesel::replace_into(download_segment_dsl::download_segment)
+                .values(segment)
+                .execute(db)?;
+        }
+        diesel::QueryResult::Ok(())
+    })?;
+    Ok(())
+}
+
+/// 删除失败记录，使这些分片在下次下载时重新尝试
+pub fn reset_failed_segments(download_id: &str) -> anyhow::Result<usize> {
+    let mut db = get_database_pool()?;
+    let rows_affected = diesel::delete(download_segment_dsl::download_segment)
+        .filter(download_segment_dsl::download_id.eq(download_id))
+        .filter(download_segment_dsl::status.eq("failed"))
+        .execute(&mut db)?;
+    Ok(rows_affected)
+}
+
+pub fn delete_segments(download_id: &str) -> anyhow::Result<usize> {
+    let mut db = get_database_pool()?;
+    let rows_affected = diesel::delete(download_segment_dsl::download_segment)
+        .filter(download_segment_dsl::download_id.eq(download_id))
+        .execute(&mut db)?;
+    Ok(rows_affected)
+}
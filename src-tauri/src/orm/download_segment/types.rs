@@ -0,0 +1,19 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Queryable, Selectable, QueryableByName, Insertable,
+)]
+#[diesel(table_name = crate::schema::download_segment)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct DownloadSegment {
+    pub download_id: String,
+    pub file_name: String,
+    // success 已完成 failed 多次重试后仍失败
+    pub status: String,
+    pub size: i64,
+    pub checksum: Option<String>,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub update_time: Option<String>,
+}
@@ -7,6 +7,8 @@ use tauri::{AppHandle, Manager};
 use tokio::sync::OnceCell;
 
 pub mod download_info;
+pub mod download_queue;
+pub mod download_segment;
 pub mod history;
 pub mod star;
 pub mod site;
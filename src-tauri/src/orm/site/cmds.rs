@@ -1,4 +1,5 @@
 use crate::{
+    download::site_headers,
     orm::{
         get_database_pool,
         site::{
@@ -25,6 +26,7 @@ pub fn insert_site(data: SiteSave) -> Result<Site, String> {
     if let Some(_old_site) = old_site {
         return Err(format!("站点已存在"));
     }
+    check_headers(&data.headers)?;
     let mut db = get_database_pool().map_err(|e| format!("获取数据库连接失败: {}", e))?;
     let position_max = site_dsl::site
         .select(max(site_dsl::position))
@@ -45,16 +47,19 @@ pub fn insert_site(data: SiteSave) -> Result<Site, String> {
         parse_mode: data.parse_mode,
         create_time: now.clone(),
         update_time: Some(now.clone()),
+        headers: data.headers,
     };
     diesel::insert_into(site_dsl::site)
         .values(&site)
         .execute(&mut db)
         .map_err(|e| format!("保存站点失败: {}", e))?;
+    site_headers::clear_header_rules();
     Ok(site)
 }
 
 #[tauri::command]
 pub fn update_site(data: SiteUpdate) -> Result<(), String> {
+    check_headers(&data.headers)?;
     let mut db = get_database_pool().map_err(|e| format!("获取数据库连接失败: {}", e))?;
     diesel::update(site_dsl::site)
         .filter(site_dsl::id.eq(&data.id))
@@ -68,10 +73,19 @@ pub fn update_site(data: SiteUpdate) -> Result<(), String> {
             site_dsl::position.eq(&data.position),
             site_dsl::is_reverse_order.eq(&data.is_reverse_order),
             site_dsl::parse_mode.eq(&data.parse_mode),
+            site_dsl::headers.eq(&data.headers),
             site_dsl::update_time.eq(utils::get_current_time_str()),
         ))
         .execute(&mut db)
         .map_err(|e| format!("更新站点失败: {}", e))?;
+    site_headers::clear_header_rules();
+    Ok(())
+}
+
+fn check_headers(headers: &Option<String>) -> Result<(), String> {
+    if let Some(headers) = headers {
+        site_headers::parse_header_rules(headers).map_err(|e| format!("请求头格式错误: {}", e))?;
+    }
     Ok(())
 }
 
@@ -82,6 +96,7 @@ pub fn delete_site(id: &str) -> Result<(), String> {
         .filter(site_dsl::id.eq(id))
         .execute(&mut db)
         .map_err(|e| format!("删除站点失败: {}", e))?;
+    site_headers::clear_header_rules();
     Ok(())
 }
 
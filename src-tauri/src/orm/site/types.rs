@@ -19,6 +19,7 @@ pub struct Site {
     pub parse_mode: Option<String>,
     pub create_time: String,
     pub update_time: Option<String>,
+    pub headers: Option<String>,
 }
 
 #[allow(non_snake_case)]
@@ -33,6 +34,7 @@ pub struct SiteSave {
     pub position: Option<f64>,
     pub is_reverse_order: String,
     pub parse_mode: Option<String>,
+    pub headers: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -47,6 +49,7 @@ pub struct SiteUpdate {
     pub position: Option<f64>,
     pub is_reverse_order: String,
     pub parse_mode: Option<String>,
+    pub headers: Option<String>,
 }
 
 #[derive(
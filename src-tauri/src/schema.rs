@@ -10,6 +10,40 @@ diesel::table! {
         download_status -> Text,
         create_time -> Text,
         update_time -> Nullable<Text>,
+        variant -> Nullable<Text>,
+        audio_renditions -> Nullable<Text>,
+        subtitle_renditions -> Nullable<Text>,
+        downloaded_bytes -> BigInt,
+        total_bytes -> Nullable<BigInt>,
+        speed -> BigInt,
+        average_speed -> BigInt,
+        eta -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    download_queue (id) {
+        id -> Text,
+        download_id -> Text,
+        priority -> Integer,
+        position -> Double,
+        status -> Text,
+        attempt_count -> Integer,
+        enqueue_time -> Text,
+        update_time -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    download_segment (download_id, file_name) {
+        download_id -> Text,
+        file_name -> Text,
+        status -> Text,
+        size -> BigInt,
+        checksum -> Nullable<Text>,
+        attempts -> Integer,
+        error -> Nullable<Text>,
+        update_time -> Nullable<Text>,
     }
 }
 
@@ -104,6 +138,7 @@ diesel::table! {
         parse_mode -> Nullable<Text>,
         create_time -> Text,
         update_time -> Nullable<Text>,
+        headers -> Nullable<Text>,
     }
 }
 
@@ -6,4 +6,6 @@ export const getDownloadInfoById = createRequest<{ id: string }, any>("get_downl
 
 export const saveDownloadInfo = createRequest<{ download_infos: any[] }, any>("save_download_info", true);
 
+export const getM3u8Variants = createRequest<{ url: string }, any>("get_m3u8_variants", true);
+
 export const deleteDownloadInfo = createRequest<{ id: string }, any>("delete_download_info", true);
@@ -50,6 +50,7 @@ export const saveSite = async (site: any) => {
         api: site.api,
         site_group: site.siteGroup,
         parse_mode: site.parseMode,
+        headers: site.headers || null,
     };
     if (siteInfo.id) {
         await updateSite(siteInfo);
@@ -1,92 +1,77 @@
+import { listen } from "@tauri-apps/api/event";
+
+// 与后端 DOWNLOAD_MESSAGE_VERSION 保持一致
+const DOWNLOAD_MESSAGE_VERSION = 1;
+
 export class DownloadBus {
-    wsAddr = "ws://127.0.0.1:8000";
-    downloadRequest = {
-        id: "0",
-        messageType: "get_download_info_by_queue",
-        downloadTaskInfo: null,
-    };
-    ws;
+    eventName = "download-message";
+    unlisten = null;
     updateDownloadInfoEvent;
-    downloadInterval;
     isCompulsionClose = false;
 
-    constructor(downloadInfo) {
-        this.downloadRequest.downloadTaskInfo = downloadInfo;
-        this.initSocket();
+    constructor() {
+        this.initEventListen();
     }
 
-    isWsOpen = () => this.ws && this.ws.readyState === 1;
-
-    intervalGetDownloadInfo = () => {
-        this.downloadInterval = setInterval(() => {
-            this.downloadRequest.downloadTaskInfo = null
-            this.downloadRequest.messageType = "get_download_info_by_queue";
-            this.ws.send(JSON.stringify(this.downloadRequest));
-        }, 1000);
-    };
-
     compulsionClose = () => {
         this.isCompulsionClose = true;
-        clearInterval(this.downloadInterval);
-        this.ws.close();
+        if (this.unlisten) {
+            this.unlisten();
+            this.unlisten = null;
+        }
     };
 
-    initSocket = () => {
-        this.ws = new WebSocket(this.wsAddr);
-        this.ws.onopen = this.intervalGetDownloadInfo;
-
-        this.ws.onclose = () => {
-            if (!this.isCompulsionClose) {
-                clearInterval(this.downloadInterval);
-                setTimeout(this.initSocket, 3000);
-            }
-        };
+    // 下载任务由后台调度，这里只接收所有任务的进度消息
+    initEventListen = async () => {
+        const unlisten = await listen(this.eventName, ({ payload }) => {
+            this.handleMessage(payload);
+        });
+        if (this.isCompulsionClose) {
+            unlisten();
+            return;
+        }
+        this.unlisten = unlisten;
+    };
 
-        this.ws.onerror = () => {
-            console.log("websocket连接失败，请刷新！");
-        };
+    handleMessage = (dataObj) => {
+        if (dataObj?.version > DOWNLOAD_MESSAGE_VERSION) {
+            console.warn(`不支持的下载消息版本: ${dataObj.version}`);
+        }
+        if (!dataObj?.id) {
+            return;
+        }
+        const downloadInfo = { id: dataObj.id };
+        if (dataObj?.status) {
+            downloadInfo.status = dataObj.status;
+        }
+        if (dataObj?.download_status) {
+            downloadInfo.download_status = dataObj.download_status;
+        }
 
-        this.ws.onmessage = async ({ data }) => {
-            const dataObj = JSON.parse(data);
-            if (dataObj.messageType === "get_download_info_by_queue") {
-                if (dataObj.downloadTaskInfo) {
-                    clearInterval(this.downloadInterval);
-                    this.downloadRequest.messageType = "downloadVideo";
-                    this.downloadRequest.downloadTaskInfo = dataObj.downloadTaskInfo;
-                    this.ws.send(JSON.stringify(this.downloadRequest));
+        switch (dataObj.mes_type) {
+            case "parseSourceEnd":
+            case "liveRefresh":
+                downloadInfo.count = dataObj.count;
+                break;
+            case "progress":
+                downloadInfo.download_count = dataObj.download_count;
+                downloadInfo.failed_count = dataObj.failed_count;
+                downloadInfo.downloaded_bytes = dataObj.downloaded_bytes;
+                downloadInfo.total_bytes = dataObj.total_bytes;
+                downloadInfo.speed = dataObj.speed;
+                downloadInfo.average_speed = dataObj.average_speed;
+                downloadInfo.eta = dataObj.eta;
+                break;
+            case "end":
+                if (dataObj.download_count !== null && dataObj.download_count !== undefined) {
+                    downloadInfo.download_count = dataObj.download_count;
                 }
-                return;
-            }
-
-            if (dataObj?.status) {
-                this.downloadRequest.downloadTaskInfo.status = dataObj.status;
-            }
-            if (dataObj?.download_status) {
-                this.downloadRequest.downloadTaskInfo.download_status = dataObj.download_status;
-            }
-
-            switch (dataObj.mes_type) {
-                case "parseSourceEnd":
-                case "parseSourceError":
-                case "downloadSliceEnd":
-                case "checkSourceEnd":
-                    if (dataObj.mes_type === "parseSourceEnd") {
-                        this.downloadRequest.downloadTaskInfo.count = dataObj.count;
-                    }
-                    break;
-                case "progress":
-                    this.downloadRequest.downloadTaskInfo.download_count = dataObj.download_count;
-                    break;
-                case "end":
-                    this.downloadRequest.downloadTaskInfo.download_status = dataObj.download_status;
-                    break;
-            }
-
-            this.updateDownloadInfoEvent(this.downloadRequest.downloadTaskInfo);
+                if (dataObj.reason) {
+                    console.error(`下载失败: ${dataObj.reason}`);
+                }
+                break;
+        }
 
-            if (dataObj.mes_type === "end") {
-                this.intervalGetDownloadInfo();
-            }
-        };
+        this.updateDownloadInfoEvent(downloadInfo);
     };
 }
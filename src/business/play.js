@@ -2,8 +2,66 @@ import DPlayer from "dplayerplus";
 import { listen } from "@tauri-apps/api/event";
 import flvjs from "flv.js";
 import Hls from "hls.js";
+import { invoke } from "@tauri-apps/api/core";
 import { debounce } from "lodash";
 
+// 站点配置的请求头按来源缓存，避免每个分片都请求后端
+const siteHeadersCache = new Map();
+
+const siteHeaders = (url) => {
+    const origin = new URL(url).origin;
+    if (!siteHeadersCache.has(origin)) {
+        siteHeadersCache.set(
+            origin,
+            invoke("get_site_headers", { url }).catch((error) => {
+                console.error("获取站点请求头失败:", error);
+                return {};
+            })
+        );
+    }
+    return siteHeadersCache.get(origin);
+};
+
+// 请求播放列表和分片时附加站点配置的请求头，WebView 不允许设置的请求头会被忽略
+const siteHeadersXhrSetup = (xhr, url) =>
+    siteHeaders(url).then((headers) => {
+        if (!xhr.readyState) {
+            xhr.open("GET", url, true);
+        }
+        Object.entries(headers).forEach(([name, value]) => {
+            try {
+                xhr.setRequestHeader(name, value);
+            } catch (error) {
+                console.warn("无法设置请求头:", name, error);
+            }
+        });
+    });
+
+// 加载播放列表后交给后端过滤插入的广告，过滤失败时使用原始播放列表
+class AdFilterPlaylistLoader extends Hls.DefaultConfig.loader {
+    load(context, config, callbacks) {
+        const onSuccess = callbacks.onSuccess;
+        callbacks.onSuccess = (response, stats, loaderContext, networkDetails) => {
+            if (typeof response.data !== "string") {
+                onSuccess(response, stats, loaderContext, networkDetails);
+                return;
+            }
+            invoke("filter_m3u8_ads", {
+                url: response.url || loaderContext.url,
+                content: response.data,
+            })
+                .then((data) =>
+                    onSuccess({ ...response, data }, stats, loaderContext, networkDetails)
+                )
+                .catch((error) => {
+                    console.error("过滤广告失败:", error);
+                    onSuccess(response, stats, loaderContext, networkDetails);
+                });
+        };
+        super.load(context, config, callbacks);
+    }
+}
+
 export class MoviesPlayer {
     playerType = "";
     dpConfig = {
@@ -155,6 +213,8 @@ export class MoviesPlayer {
                         const hls = new Hls({
                             debug: false,
                             p2pConfig: { live: false },
+                            pLoader: AdFilterPlaylistLoader,
+                            xhrSetup: siteHeadersXhrSetup,
                         });
                         hls.loadSource(video.src);
                         hls.attachMedia(video);
@@ -0,0 +1,133 @@
+import React, { useEffect, useState } from 'react';
+import { Modal, Select } from 'antd';
+
+const formatBandwidth = (bandwidth) => `${(bandwidth / 1000000).toFixed(1)} Mbps`;
+
+// 选择值会保存到每一集的 variant 中，按分辨率匹配，所以同一分辨率只保留一个选项
+const variantOptions = (variants) => {
+    const options = [
+        { value: "", label: "默认" },
+        { value: "highestBandwidth", label: "最高码率" },
+        { value: "lowestBandwidth", label: "最低码率" },
+    ];
+    variants.forEach((v) => {
+        if (v.variant && !options.some((o) => o.value === v.variant)) {
+            options.push({
+                value: v.variant,
+                label: `${v.resolution} · ${formatBandwidth(v.bandwidth)}`,
+            });
+        }
+    });
+    return options;
+};
+
+// 音轨、字幕按 NAME 匹配，不同分组中的同名轨道合并为一个选项
+const renditionOptions = (renditions, mediaType) => {
+    const options = [
+        { value: "default", label: "默认" },
+        { value: "all", label: "全部" },
+    ];
+    renditions
+        .filter((r) => r.media_type === mediaType)
+        .forEach((r) => {
+            if (!options.some((o) => o.value === r.name)) {
+                options.push({
+                    value: r.name,
+                    label: r.language ? `${r.name} (${r.language})` : r.name,
+                });
+            }
+        });
+    return options;
+};
+
+// default、all 与具体名称互斥，最后选择的生效
+const renditionChange = (setValue) => (values) => {
+    const last = values[values.length - 1];
+    if (last === "default" || last === "all") {
+        setValue([last]);
+    } else {
+        setValue(values.filter((v) => v !== "default" && v !== "all"));
+    }
+};
+
+/**
+ * 下载选项弹窗，m3u8 主播放列表包含多个媒体流或独立的音轨、字幕时选择下载内容
+ * @param {Object} props - 组件属性
+ * @param {boolean} props.visible - 弹窗是否可见
+ * @param {Object} props.streams - get_m3u8_variants 返回的媒体流列表
+ * @param {Function} props.onOk - 确认回调，参数为 { variant, audio_renditions, subtitle_renditions }
+ * @param {Function} props.onCancel - 取消回调
+ */
+const DownloadOptionsModal = ({ visible, streams, onOk, onCancel }) => {
+    const [variant, setVariant] = useState("");
+    // 不选择时不下载独立的音轨和字幕
+    const [audioRenditions, setAudioRenditions] = useState([]);
+    const [subtitleRenditions, setSubtitleRenditions] = useState([]);
+    const renditions = streams?.renditions ?? [];
+    const hasAudio = renditions.some((r) => r.media_type === "audio");
+    const hasSubtitles = renditions.some((r) => r.media_type === "subtitles");
+
+    useEffect(() => {
+        if (visible) {
+            setVariant("");
+            setAudioRenditions([]);
+            setSubtitleRenditions([]);
+        }
+    }, [visible]);
+
+    const handleOk = () => {
+        onOk({
+            variant: variant || null,
+            audio_renditions: audioRenditions.join(",") || null,
+            subtitle_renditions: subtitleRenditions.join(",") || null,
+        });
+    };
+
+    return (
+        <Modal
+            title="下载选项"
+            open={visible}
+            onOk={handleOk}
+            onCancel={onCancel}
+            okText="下载"
+            cancelText="取消"
+            centered
+        >
+            <div style={{ marginBottom: '8px' }}>清晰度</div>
+            <Select
+                style={{ width: '100%' }}
+                value={variant}
+                options={variantOptions(streams?.variants ?? [])}
+                onChange={setVariant}
+            />
+            {hasAudio && (
+                <>
+                    <div style={{ margin: '16px 0 8px' }}>音轨</div>
+                    <Select
+                        mode="multiple"
+                        style={{ width: '100%' }}
+                        placeholder="不下载独立音轨"
+                        value={audioRenditions}
+                        options={renditionOptions(renditions, "audio")}
+                        onChange={renditionChange(setAudioRenditions)}
+                    />
+                </>
+            )}
+            {hasSubtitles && (
+                <>
+                    <div style={{ margin: '16px 0 8px' }}>字幕</div>
+                    <Select
+                        mode="multiple"
+                        style={{ width: '100%' }}
+                        placeholder="不下载字幕"
+                        value={subtitleRenditions}
+                        options={renditionOptions(renditions, "subtitles")}
+                        onChange={renditionChange(setSubtitleRenditions)}
+                    />
+                </>
+            )}
+        </Modal>
+    );
+};
+
+export default DownloadOptionsModal;
@@ -1,8 +1,10 @@
+import { useState } from "react";
 import { useGlobalStore } from "@/store/useGlobalStore";
 import LazyImage from "@/components/LazyImage";
+import DownloadOptionsModal from "@/components/DownloadOptionsModal";
 import doubanApi from "@/api/douban";
 import { getSiteByKey } from "@/api/site";
-import { saveDownloadInfo } from "@/api/downloadInfo";
+import { getM3u8Variants, saveDownloadInfo } from "@/api/downloadInfo";
 import { starMovie } from "@/api/star";
 import { message } from "antd";
 import { fmtMSS } from "@/utils/common";
@@ -18,6 +20,8 @@ const MovieCard = ({ key, item, layoutHandle, site, viewMode = "default", showSi
     const toggleMovieDetailInfo = useMovieStore((state) => state.toggleMovieDetailInfo);
     const togglePlayInfo = useGlobalStore((state) => state.togglePlayInfo);
     const siteMap = useGlobalStore((state) => state.siteMap);
+    // 等待选择下载选项的剧集
+    const [pendingDownload, setPendingDownload] = useState(null);
 
     const imgLoad = () => layoutHandle();
 
@@ -104,6 +108,11 @@ const MovieCard = ({ key, item, layoutHandle, site, viewMode = "default", showSi
                 parentId: "0",
                 download_status: "wait",
             }));
+            const streams = await getDownloadStreams(downloadInfos[0]?.url);
+            if (streams?.variants?.length > 1 || streams?.renditions?.length > 0) {
+                setPendingDownload({ downloadInfos, streams, info: res.info });
+                return;
+            }
             saveDownloadInfo({downloadInfos});
             messageApi.success(res.info);
         } catch (err) {
@@ -112,6 +121,30 @@ const MovieCard = ({ key, item, layoutHandle, site, viewMode = "default", showSi
         }
     };
 
+    // 只解析 m3u8 的主播放列表，其他地址直接下载
+    const getDownloadStreams = async (url) => {
+        if (!url || !new URL(url, "http://localhost").pathname.endsWith(".m3u8")) {
+            return null;
+        }
+        try {
+            return await getM3u8Variants({ url });
+        } catch (err) {
+            console.log(err);
+            return null;
+        }
+    };
+
+    // 所有剧集使用同一个选择，按分辨率匹配各集的媒体流
+    const downloadOptionsOk = (options) => {
+        const downloadInfos = pendingDownload.downloadInfos.map((downloadInfo) => ({
+            ...downloadInfo,
+            ...options,
+        }));
+        saveDownloadInfo({downloadInfos});
+        messageApi.success(pendingDownload.info);
+        setPendingDownload(null);
+    };
+
     const deleteEvent = (e) => {
         e.stopPropagation();
         if (onDelete) {
@@ -223,6 +256,14 @@ const MovieCard = ({ key, item, layoutHandle, site, viewMode = "default", showSi
             </div>
             <div className="name">{getName()}</div>
             <div className="info">{getInfoDom()}</div>
+            <div onClick={(e) => e.stopPropagation()}>
+                <DownloadOptionsModal
+                    visible={pendingDownload !== null}
+                    streams={pendingDownload?.streams}
+                    onOk={downloadOptionsOk}
+                    onCancel={() => setPendingDownload(null)}
+                />
+            </div>
         </div>
     );
 };
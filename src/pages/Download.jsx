@@ -2,11 +2,12 @@ import { useEffect } from "react";
 import { useGlobalStore } from "@/store/useGlobalStore";
 import { useMovieStore } from "@/store/useMovieStore";
 import { selectAllDownloadInfo, deleteDownloadInfo } from "@/api/downloadInfo";
-import { Progress, Table, Space, Tag } from "antd";
+import { Progress, Table, Space, Tag, message } from "antd";
 import { invoke } from "@tauri-apps/api/core";
 import _ from "lodash";
 import "./Download.scss";
 import { useConfig } from "@/hooks";
+import { formatBytes, formatEta } from "@/utils/common";
 
 const LinearProgressWithLabel = (props) => (
   <div style={{ display: "flex", alignItems: "center" }}>
@@ -33,6 +34,12 @@ const Download = (props) => {
   };
 
   const getPercentage = (rowData) => {
+    // 分片大小差别较大，能估算总大小时按字节计算，分片全部完成前不显示 100%
+    if (rowData.total_bytes > 0 && rowData.download_count < rowData.count) {
+      const percentageNum =
+        _.divide(rowData.downloaded_bytes, rowData.total_bytes) * 100;
+      return _.min([_.ceil(percentageNum, 2), 99.99]);
+    }
     const percentageNum =
       rowData.count === 0
         ? 0.0
@@ -40,11 +47,32 @@ const Download = (props) => {
     return _.ceil(percentageNum, 2);
   };
 
+  const getTransferInfo = (record) => {
+    if (!record.downloaded_bytes) return null;
+    const size = record.total_bytes
+      ? `${formatBytes(record.downloaded_bytes)} / ${formatBytes(record.total_bytes)}`
+      : formatBytes(record.downloaded_bytes);
+    if (record.download_status !== "downloading") {
+      return <div className="transfer-info">{size}</div>;
+    }
+    return (
+      <div className="transfer-info">
+        {size}
+        {` · ${formatBytes(record.speed)}/s`}
+        {record.average_speed > 0 && ` · 平均 ${formatBytes(record.average_speed)}/s`}
+        {record.eta !== null && record.eta !== undefined && ` · 剩余 ${formatEta(record.eta)}`}
+      </div>
+    );
+  };
+
   const getDownloadStatus = (downloadStatus) => {
     const statusMap = {
       wait: { color: "info", label: "等待下载" },
       downloading: { color: "secondary", label: "下载中" },
+      paused: { color: "warning", label: "已暂停" },
+      cancelled: { color: "default", label: "已取消" },
       downloadFail: { color: "error", label: "下载失败" },
+      downloadPartial: { color: "warning", label: "部分分片失败" },
       downloadSuccess: { color: "success", label: "下载成功" },
     };
     const status = statusMap[downloadStatus];
@@ -59,6 +87,7 @@ const Download = (props) => {
     const statusMap = {
       parseSource: "解析资源",
       downloadSlice: "下载ts分片",
+      recordLive: "录制直播",
       checkSource: "检测完整性",
       merger: "合并视频",
       downloadEnd: "下载结束",
@@ -97,14 +126,26 @@ const Download = (props) => {
       dataIndex: "download_progress",
       key: "download_progress",
       render: (_, record) => (
-        <LinearProgressWithLabel percent={getPercentage(record)} />
+        <>
+          <LinearProgressWithLabel percent={getPercentage(record)} />
+          {getTransferInfo(record)}
+        </>
       ),
     },
     {
       title: "下载状态",
       dataIndex: "download_status",
       key: "download_status",
-      render: (download_status) => getDownloadStatus(download_status),
+      render: (download_status, record) => (
+        <>
+          {getDownloadStatus(download_status)}
+          {record.failed_count > 0 && (
+            <Tag color="warning" bordered={false}>
+              {`失败分片 ${record.failed_count}`}
+            </Tag>
+          )}
+        </>
+      ),
     },
     {
       title: "操作",
@@ -116,7 +157,28 @@ const Download = (props) => {
           {record.download_status === "downloadSuccess" && (
             <a onClick={() => playEvent(record)}>播放</a>
           )}
-          {record.status !== "downloadEnd" && (
+          {record.status === "recordLive" && (
+            <a onClick={() => stopLiveRecord(record)}>停止录制</a>
+          )}
+          {record.download_status === "wait" && (
+            <a onClick={() => topDownload(record)}>置顶</a>
+          )}
+          {record.download_status === "downloading" && (
+            <a onClick={() => pauseDownload(record)}>暂停</a>
+          )}
+          {(record.download_status === "paused" ||
+            record.download_status === "cancelled") && (
+            <a onClick={() => resumeDownload(record)}>继续</a>
+          )}
+          {(record.download_status === "downloading" ||
+            record.download_status === "wait" ||
+            record.download_status === "paused") && (
+            <>
+              <a onClick={() => cancelDownload(record, false)}>取消</a>
+              <a onClick={() => cancelDownload(record, true)}>取消并删除文件</a>
+            </>
+          )}
+          {record.status !== "downloadEnd" && record.status !== "recordLive" && (
             <>
               <a onClick={() => movieMerger(record)}>合并</a>
             </>
@@ -127,6 +189,12 @@ const Download = (props) => {
               <a onClick={() => movieMerger(record)}>强制合并</a>
             </>
           )}
+          {record.download_status === "downloadPartial" && (
+            <>
+              <a onClick={() => retryFailedSegments(record)}>重试失败分片</a>
+              <a onClick={() => movieMerger(record)}>强制合并</a>
+            </>
+          )}
           <a onClick={() => onDeleteDownload(record)}>删除</a>
         </Space>
       ),
@@ -149,14 +217,58 @@ const Download = (props) => {
   const retryEvent = async (download) => {
     const downloadInfo = {
       ...download,
-      download_status: "downloading",
+      download_status: "wait",
       save_path: downloadSavePath,
     };
     await invoke("retry_download", { download: downloadInfo });
+    updateDownloadInfoProcess(downloadInfo);
+  };
+
+  // 提高优先级，排到下载队列最前面
+  const topDownload = async (download) => {
+    const queue = await invoke("select_download_queue");
+    const priority = _.max(queue.map((item) => item.priority)) ?? 0;
+    await invoke("update_download_queue_priority", {
+      downloadId: download.id,
+      priority: priority + 1,
+    });
+  };
+
+  const retryFailedSegments = async (download) => {
+    const downloadInfo = {
+      ...download,
+      download_status: "downloading",
+      save_path: downloadSavePath,
+    };
+    await invoke("retry_failed_segments", { download: downloadInfo });
+  };
+
+  const pauseDownload = async (download) => {
+    await invoke("pause_download", { id: download.id });
+  };
+
+  const resumeDownload = async (download) => {
+    await invoke("resume_download", { download });
+    updateDownloadInfoProcess({ ...download, download_status: "wait" });
+  };
+
+  const cancelDownload = async (download, deleteFiles) => {
+    await invoke("cancel_download", { download, deleteFiles });
+    init();
+  };
+
+  const stopLiveRecord = async (download) => {
+    await invoke("stop_live_record", { id: download.id });
   };
 
   const movieMerger = async (download) => {
-    const di = await invoke("movie_merger", { download });
+    let di;
+    try {
+      di = await invoke("movie_merger", { download });
+    } catch (err) {
+      message.error(`${err}`);
+      return;
+    }
     if (di) {
       const diInfo = _.cloneDeep(download);
       diInfo.download_status = di.download_status;
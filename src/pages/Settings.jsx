@@ -69,6 +69,56 @@ const Settings = (props) => {
         "downloadSavePath",
         ""
     );
+    const [liveRecordMaxDuration, setLiveRecordMaxDuration] = useConfig(
+        "liveRecordMaxDuration",
+        0
+    );
+    const [mergeMode, setMergeMode] = useConfig("mergeMode", "ffmpeg");
+    const [downloadGlobalConcurrency, setDownloadGlobalConcurrency] =
+        useConfig("downloadGlobalConcurrency", 16);
+    const [downloadTaskConcurrency, setDownloadTaskConcurrency] = useConfig(
+        "downloadTaskConcurrency",
+        6
+    );
+    const [downloadConnections, setDownloadConnections] = useConfig(
+        "downloadConnections",
+        4
+    );
+    const [downloadMaxTasks, setDownloadMaxTasks] = useConfig(
+        "downloadMaxTasks",
+        2
+    );
+    const [downloadTimeout, setDownloadTimeout] = useConfig(
+        "downloadTimeout",
+        10
+    );
+    const [downloadUserAgent, setDownloadUserAgent] = useConfig(
+        "downloadUserAgent",
+        "pc"
+    );
+    const [downloadServerPort, setDownloadServerPort] = useConfig(
+        "downloadServerPort",
+        8000
+    );
+    const [downloadServerToken, setDownloadServerToken] = useState("");
+    const [adFilterEnabled, setAdFilterEnabled] = useConfig(
+        "adFilterEnabled",
+        false
+    );
+    const [adFilterRules, setAdFilterRules] = useConfig("adFilterRules", []);
+    const [adFilterRulesText, setAdFilterRulesText] = useState("");
+    const [segmentRetryCount, setSegmentRetryCount] = useConfig(
+        "segmentRetryCount",
+        5
+    );
+    const [downloadBandwidthLimit, setDownloadBandwidthLimit] = useConfig(
+        "downloadBandwidthLimit",
+        0
+    );
+    const [downloadTaskBandwidthLimit, setDownloadTaskBandwidthLimit] =
+        useConfig("downloadTaskBandwidthLimit", 0);
+    const [downloadBandwidthSchedule, setDownloadBandwidthSchedule] =
+        useConfig("downloadBandwidthSchedule", "");
     const [proxyProtocol, setProxyProtocol] = useConfig("proxyProtocol", "");
     const [proxyServer, setProxyServer] = useConfig("proxyServer", "");
     const [proxyPort, setProxyPort] = useConfig("proxyPort", "");
@@ -180,8 +230,27 @@ const Settings = (props) => {
         // 获取本机IP地址
         getLocalIpAddress();
 
+        // 外部客户端连接令牌，每次启动重新生成
+        invoke("get_download_server_token").then(setDownloadServerToken);
     }, []);
 
+    useEffect(() => {
+        setAdFilterRulesText(JSON.stringify(adFilterRules ?? [], null, 2));
+    }, [adFilterRules]);
+
+    // 规则为 JSON 数组，格式错误时不保存
+    const adFilterRulesBlur = () => {
+        try {
+            const rules = JSON.parse(adFilterRulesText || "[]");
+            if (!Array.isArray(rules)) {
+                throw new Error("规则必须是数组");
+            }
+            setAdFilterRules(rules);
+        } catch (error) {
+            messageApi.error("广告过滤规则格式错误: " + error.message);
+        }
+    };
+
     const linkOpen = (url) => {
         open(url);
     };
@@ -227,6 +296,19 @@ const Settings = (props) => {
         setDownloadSavePath(downloadSavePath);
     };
 
+    const mergeModeSelectData = () => {
+        return [
+            {
+                name: "FFmpeg",
+                value: "ffmpeg",
+            },
+            {
+                name: "直接拼接",
+                value: "native",
+            },
+        ];
+    };
+
     const proxyProtocolSelectData = () => {
         return [
             {
@@ -847,6 +929,257 @@ const Settings = (props) => {
                                 downloadSavePathCallback(downloadSavePath)
                             }
                         />
+                        <SettingsSelect
+                            title="视频合并方式"
+                            initValue={mergeMode}
+                            selectData={mergeModeSelectData()}
+                            callback={(mergeMode) => setMergeMode(mergeMode)}
+                        />
+                        <div className="item">
+                            <div className="left">
+                                <div className="title">同时下载任务数</div>
+                                <div className="description">
+                                    下载队列中同时开始下载的任务数
+                                </div>
+                            </div>
+                            <div className="right">
+                                <input
+                                    value={downloadMaxTasks ?? 2}
+                                    className="text-input"
+                                    placeholder="2"
+                                    type="number"
+                                    min={1}
+                                    onChange={(e) =>
+                                        setDownloadMaxTasks(
+                                            e.target.valueAsNumber || 1
+                                        )
+                                    }
+                                />
+                            </div>
+                        </div>
+                        <div className="item">
+                            <div className="left">
+                                <div className="title">外部客户端端口</div>
+                                <div className="description">
+                                    供外部客户端提交下载的 WebSocket 端口，0 表示不启用，重启后生效
+                                    {downloadServerPort > 0 && downloadServerToken && (
+                                        <div className="selectable">
+                                            ws://127.0.0.1:{downloadServerPort}/?token=
+                                            {downloadServerToken}
+                                        </div>
+                                    )}
+                                </div>
+                            </div>
+                            <div className="right">
+                                <input
+                                    value={downloadServerPort ?? 8000}
+                                    className="text-input"
+                                    placeholder="8000"
+                                    type="number"
+                                    min={0}
+                                    max={65535}
+                                    onChange={(e) =>
+                                        setDownloadServerPort(
+                                            e.target.valueAsNumber || 0
+                                        )
+                                    }
+                                />
+                            </div>
+                        </div>
+                        <div className="item">
+                            <div className="left">
+                                <div className="title">分片下载并发数</div>
+                                <div className="description">
+                                    全局并发数 / 单个任务并发数，下载中修改立即生效
+                                </div>
+                            </div>
+                            <div className="right">
+                                <input
+                                    value={downloadGlobalConcurrency ?? 16}
+                                    className="text-input"
+                                    placeholder="16"
+                                    type="number"
+                                    min={1}
+                                    onChange={(e) =>
+                                        setDownloadGlobalConcurrency(
+                                            e.target.valueAsNumber || 1
+                                        )
+                                    }
+                                />
+                                <input
+                                    value={downloadTaskConcurrency ?? 6}
+                                    className="text-input"
+                                    placeholder="6"
+                                    type="number"
+                                    min={1}
+                                    onChange={(e) =>
+                                        setDownloadTaskConcurrency(
+                                            e.target.valueAsNumber || 1
+                                        )
+                                    }
+                                />
+                            </div>
+                        </div>
+                        <div className="item">
+                            <div className="left">
+                                <div className="title">视频文件连接数</div>
+                                <div className="description">
+                                    直接下载 MP4、FLV 等视频文件时同时使用的连接数，对新任务生效
+                                </div>
+                            </div>
+                            <div className="right">
+                                <input
+                                    value={downloadConnections ?? 4}
+                                    className="text-input"
+                                    placeholder="4"
+                                    type="number"
+                                    min={1}
+                                    onChange={(e) =>
+                                        setDownloadConnections(
+                                            e.target.valueAsNumber || 1
+                                        )
+                                    }
+                                />
+                            </div>
+                        </div>
+                        <div className="item">
+                            <div className="left">
+                                <div className="title">请求超时</div>
+                                <div className="description">
+                                    下载播放列表、密钥和分片的超时时间，单位秒
+                                </div>
+                            </div>
+                            <div className="right">
+                                <input
+                                    value={downloadTimeout ?? 10}
+                                    className="text-input"
+                                    placeholder="10"
+                                    type="number"
+                                    min={1}
+                                    onChange={(e) =>
+                                        setDownloadTimeout(
+                                            e.target.valueAsNumber || 10
+                                        )
+                                    }
+                                />
+                            </div>
+                        </div>
+                        <div className="item">
+                            <div className="left">
+                                <div className="title">下载 User-Agent</div>
+                                <div className="description">
+                                    填写 pc 或 mobile 时随机使用对应平台的浏览器标识，也可以填写完整的 User-Agent
+                                </div>
+                            </div>
+                            <div className="right">
+                                <input
+                                    value={downloadUserAgent ?? "pc"}
+                                    className="text-input"
+                                    placeholder="pc"
+                                    onChange={(e) =>
+                                        setDownloadUserAgent(e.target.value)
+                                    }
+                                />
+                            </div>
+                        </div>
+                        <div className="item">
+                            <div className="left">
+                                <div className="title">下载限速</div>
+                                <div className="description">
+                                    全局限速 / 单个任务限速，单位 KB/s，0 表示不限速
+                                </div>
+                            </div>
+                            <div className="right">
+                                <input
+                                    value={downloadBandwidthLimit ?? 0}
+                                    className="text-input"
+                                    placeholder="0"
+                                    type="number"
+                                    min={0}
+                                    onChange={(e) =>
+                                        setDownloadBandwidthLimit(
+                                            e.target.valueAsNumber || 0
+                                        )
+                                    }
+                                />
+                                <input
+                                    value={downloadTaskBandwidthLimit ?? 0}
+                                    className="text-input"
+                                    placeholder="0"
+                                    type="number"
+                                    min={0}
+                                    onChange={(e) =>
+                                        setDownloadTaskBandwidthLimit(
+                                            e.target.valueAsNumber || 0
+                                        )
+                                    }
+                                />
+                            </div>
+                        </div>
+                        <div className="item">
+                            <div className="left">
+                                <div className="title">限速时间表</div>
+                                <div className="description">
+                                    命中时段时替换全局限速，如 09:00-18:00=2048;22:00-02:00=0
+                                </div>
+                            </div>
+                            <div className="right">
+                                <input
+                                    value={downloadBandwidthSchedule ?? ""}
+                                    className="text-input"
+                                    placeholder="09:00-18:00=2048"
+                                    onChange={(e) =>
+                                        setDownloadBandwidthSchedule(
+                                            e.target.value
+                                        )
+                                    }
+                                />
+                            </div>
+                        </div>
+                        <div className="item">
+                            <div className="left">
+                                <div className="title">分片重试次数</div>
+                                <div className="description">
+                                    单个分片的最大尝试次数，超过后标记为失败分片
+                                </div>
+                            </div>
+                            <div className="right">
+                                <input
+                                    value={segmentRetryCount ?? 5}
+                                    className="text-input"
+                                    placeholder="5"
+                                    type="number"
+                                    min={1}
+                                    onChange={(e) =>
+                                        setSegmentRetryCount(
+                                            e.target.valueAsNumber || 1
+                                        )
+                                    }
+                                />
+                            </div>
+                        </div>
+                        <div className="item">
+                            <div className="left">
+                                <div className="title">直播录制时长上限</div>
+                                <div className="description">
+                                    单位分钟，0 表示录制到直播结束或手动停止
+                                </div>
+                            </div>
+                            <div className="right">
+                                <input
+                                    value={liveRecordMaxDuration ?? 0}
+                                    className="text-input"
+                                    placeholder="0"
+                                    type="number"
+                                    min={0}
+                                    onChange={(e) =>
+                                        setLiveRecordMaxDuration(
+                                            e.target.valueAsNumber || 0
+                                        )
+                                    }
+                                />
+                            </div>
+                        </div>
                         <div className="item">
                             <div className="left">
                                 <div className="title">FFmpeg 工具</div>
@@ -982,6 +1315,44 @@ const Settings = (props) => {
                         )}
                     </>
                 )}
+                {!osType.startsWith("web") && (
+                    <>
+                        <h3>广告过滤</h3>
+                        <SettingsSwitch
+                            title="过滤插入的广告"
+                            description="根据不连续标记、分片域名和时长特征过滤 m3u8 中的广告，对播放和下载生效"
+                            initValue={adFilterEnabled}
+                            fieldKey="adFilterEnabled"
+                            callback={(switchValue) =>
+                                setAdFilterEnabled(switchValue)
+                            }
+                        />
+                        {adFilterEnabled && (
+                            <div className="item">
+                                <div className="left">
+                                    <div className="title">站点规则</div>
+                                    <div className="description">
+                                        按播放地址域名匹配，host 为 * 时对所有站点生效，没有匹配的规则时不过滤，可设置
+                                        maxAdDuration、checkHost、checkPath、durations、patterns，checkHost
+                                        和 checkPath 会误删多 CDN 的正片，默认关闭
+                                    </div>
+                                </div>
+                                <div className="right">
+                                    <textarea
+                                        className="text-input"
+                                        rows={6}
+                                        placeholder='[{"host": "example.com", "maxAdDuration": 120, "checkHost": true, "durations": [], "patterns": []}]'
+                                        value={adFilterRulesText}
+                                        onChange={(e) =>
+                                            setAdFilterRulesText(e.target.value)
+                                        }
+                                        onBlur={adFilterRulesBlur}
+                                    />
+                                </div>
+                            </div>
+                        )}
+                    </>
+                )}
                 {!osType.startsWith("web") && (
                     <>
                         <h3>代理配置</h3>
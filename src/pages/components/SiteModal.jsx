@@ -18,6 +18,7 @@ const SiteModal = ({ siteInfo, open, onClose, onSubmit }) => {
           siteName: siteInfo.site_name,
           api: siteInfo.api,
           parseMode: siteInfo.parse_mode,
+          headers: siteInfo.headers || "",
         });
       } else {
         form.setFieldsValue({
@@ -26,6 +27,7 @@ const SiteModal = ({ siteInfo, open, onClose, onSubmit }) => {
           siteName: "",
           api: "",
           parseMode: "xml",
+          headers: "",
         });
       }
   }
@@ -128,6 +130,28 @@ const SiteModal = ({ siteInfo, open, onClose, onSubmit }) => {
             <Select.Option value="json">json</Select.Option>
           </Select>
         </Form.Item>
+        <Form.Item
+          label="请求头"
+          name="headers"
+          tooltip='播放列表、密钥和分片请求附带的请求头，例如 [{"host":"*.example.com","headers":{"Referer":"https://example.com/"}}]'
+          rules={[
+            {
+              validator: (_, value) => {
+                if (!value || !value.trim()) {
+                  return Promise.resolve();
+                }
+                try {
+                  if (Array.isArray(JSON.parse(value))) {
+                    return Promise.resolve();
+                  }
+                } catch (e) {}
+                return Promise.reject(new Error("请输入 JSON 数组"));
+              },
+            },
+          ]}
+        >
+          <Input.TextArea rows={3} />
+        </Form.Item>
         <Form.Item label={null}>
           <Button type="primary" htmlType="submit">
             提交
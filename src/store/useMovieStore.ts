@@ -16,6 +16,12 @@ export interface DownloadInfo {
     download_count: number;
     count: number;
     download_status: string;
+    failed_count?: number;
+    downloaded_bytes?: number;
+    total_bytes?: number | null;
+    speed?: number;
+    average_speed?: number;
+    eta?: number | null;
 }
 
 export interface HistoryInfo {
@@ -61,18 +67,32 @@ export const useMovieStore = create<MovieState>()(persist(
     },
     updateDownloadInfoProcess: (downloadInfo: DownloadInfo) => {
         set((state) => {
-            const { id, count, download_count, status, download_status } =
+            const { id, count, download_count, failed_count, status, download_status } =
                 downloadInfo;
             let downloading = find(state.downloadInfoList, { id: id });
             if (!downloading) return state;
             if (count) {
                 downloading.count = count;
             }
-            if (download_count) {
+            if (download_count !== undefined) {
                 downloading.download_count = download_count;
             }
-            downloading.status = status;
-            downloading.download_status = download_status;
+            if (failed_count !== undefined) {
+                downloading.failed_count = failed_count;
+            }
+            // 进度消息中的字节数、速度和剩余时间
+            const progressKeys = ["downloaded_bytes", "total_bytes", "speed", "average_speed", "eta"] as const;
+            progressKeys.forEach((key) => {
+                if (downloadInfo[key] !== undefined) {
+                    (downloading as any)[key] = downloadInfo[key];
+                }
+            });
+            if (status) {
+                downloading.status = status;
+            }
+            if (download_status) {
+                downloading.download_status = download_status;
+            }
             return {
                 downloadInfoList: state.downloadInfoList.map((item) => {
                     if (item.id === id) {
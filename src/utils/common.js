@@ -35,4 +35,26 @@ export function formatSecondsToMinSec(totalSeconds) {
         min: String(minutes).padStart(2, "0"),
         sec: String(seconds).padStart(2, "0")
     };
-}
\ No newline at end of file
+}
+/**
+ * 将字节数格式化为可读的大小
+ * @param {number} bytes - 字节数
+ * @returns {string} 例如 12.5 MB
+ */
+export function formatBytes(bytes) {
+    if (!bytes || bytes <= 0) return "0 B";
+    const units = ["B", "KB", "MB", "GB", "TB"];
+    const index = Math.min(Math.floor(Math.log(bytes) / Math.log(1024)), units.length - 1);
+    return `${(bytes / Math.pow(1024, index)).toFixed(index === 0 ? 0 : 1)} ${units[index]}`;
+}
+
+/**
+ * 将剩余秒数格式化为时:分:秒
+ * @param {number} totalSeconds - 总秒数
+ * @returns {string} 例如 01:02:03 或 02:03
+ */
+export function formatEta(totalSeconds) {
+    const hours = Math.floor(totalSeconds / 3600);
+    const { min, sec } = formatSecondsToMinSec(totalSeconds % 3600);
+    return hours > 0 ? `${String(hours).padStart(2, "0")}:${min}:${sec}` : `${min}:${sec}`;
+}
@@ -17,8 +17,7 @@ import Site from "@/pages/Site";
 import Detail from "@/pages/Detail";
 import { useMovieStore } from "@/store/useMovieStore";
 
-const downloadWebsocketNum = 2;
-let downloadBusArr = [];
+let downloadBus = null;
 
 function Main() {
   const pageActive = useGlobalStore((state) => state.pageActive);
@@ -27,13 +26,10 @@ function Main() {
   const main = useRef(null);
 
   const initDownloadWebsocket = () => {
-    for (var i = 0; i < downloadWebsocketNum; i++) {
-      const downloadBus = new DownloadBus();
-      downloadBus.updateDownloadInfoEvent = (download) => {
-        updateDownloadInfoProcess(download);
-      };
-      downloadBusArr.push(downloadBus);
-    }
+    downloadBus = new DownloadBus();
+    downloadBus.updateDownloadInfoEvent = (download) => {
+      updateDownloadInfoProcess(download);
+    };
   };
 
   useEffect(() => {
@@ -43,10 +39,8 @@ function Main() {
 
     return () => {
       if (osType === "desktop") {
-        downloadBusArr.forEach((item) => {
-          item.compulsionClose();
-        });
-        downloadBusArr = [];
+        downloadBus?.compulsionClose();
+        downloadBus = null;
       }
     };
   }, []);